@@ -0,0 +1,101 @@
+// Caches the open ports found by a scan so running the same scan
+// again within --cache-ttl can skip probing everything. Entries live
+// under badmap/ in the user's cache directory, one file per scan
+use crate::sign::sha256_hex;
+use badmap::{PortResult, ScanOptions};
+
+use serde::{Deserialize, Serialize};
+
+use std::env;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Serialize, Deserialize)]
+struct CachedPort {
+    port: u16,
+    banner: Option<String>,
+    latency_us: Option<u64>,
+
+    // caches from before resets could count as open don't have it
+    #[serde(default)]
+    reset: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    // seconds since the epoch when the scan finished
+    cached_at: u64,
+    open_ports: Vec<CachedPort>,
+}
+
+// $XDG_CACHE_HOME/badmap, falling back on ~/.cache
+fn cache_dir() -> Option<PathBuf> {
+    let cache_dir: PathBuf = match env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+    };
+
+    Some(cache_dir.join("badmap"))
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |now| now.as_secs())
+}
+
+// names the cache entry for a scan, anything that could change which
+// ports come back open has to be part of it. The hash is SHA-256 so
+// the same scan keeps the same name whatever BadMap was built with
+pub fn cache_key(target: &str, ports: &[u16], options: &ScanOptions, extra: &str) -> String {
+    let ports: Vec<String> = ports.iter().map(|port| port.to_string()).collect();
+    let key: String = format!("{}\n{}\n{}\n{}", target, ports.join(","), options.result_key(), extra);
+
+    sha256_hex(key.as_bytes())
+}
+
+// the open ports from the cached scan under key and how long ago it
+// was, if there is one and it's younger than ttl. A cache that can't
+// be read just means scanning again
+pub fn load_cached(key: &str, ttl: Duration) -> Option<(Duration, Vec<PortResult>)> {
+    let contents: String = std::fs::read_to_string(cache_dir()?.join(format!("{}.json", key))).ok()?;
+    let entry: CacheEntry = serde_json::from_str(&contents).ok()?;
+
+    let age: Duration = Duration::from_secs(now().saturating_sub(entry.cached_at));
+    if age > ttl {
+        return None;
+    }
+
+    let open_ports: Vec<PortResult> = entry
+        .open_ports
+        .into_iter()
+        .map(|port| PortResult {
+            port: port.port,
+            error: None,
+            banner: port.banner,
+            latency: port.latency_us.map(Duration::from_micros),
+            reset: port.reset,
+        })
+        .collect();
+
+    Some((age, open_ports))
+}
+
+// saves the open ports of a finished scan under key
+pub fn store(key: &str, open_ports: &[PortResult]) -> Result<(), String> {
+    let dir: PathBuf = cache_dir().ok_or("no home directory to keep the cache in")?;
+
+    let entry = CacheEntry {
+        cached_at: now(),
+        open_ports: open_ports
+            .iter()
+            .map(|result| CachedPort {
+                port: result.port,
+                banner: result.banner.clone(),
+                latency_us: result.latency.map(|latency| latency.as_micros() as u64),
+                reset: result.reset,
+            })
+            .collect(),
+    };
+
+    std::fs::create_dir_all(&dir).map_err(|err| err.to_string())?;
+    std::fs::write(dir.join(format!("{}.json", key)), serde_json::to_string(&entry).unwrap()).map_err(|err| err.to_string())
+}
@@ -0,0 +1,63 @@
+// Ranges that belong to the big CDNs and cloud fronts, scanning
+// an address in one of these reaches the provider's edge rather
+// than whatever server is behind it
+use crate::Cidr;
+
+use std::net::IpAddr;
+
+// the published IPv4 ranges of each provider, these change over
+// time so the list is only ever a best guess
+pub static CDN_RANGES: &[(&str, &str)] = &[
+    ("Cloudflare", "173.245.48.0/20"),
+    ("Cloudflare", "103.21.244.0/22"),
+    ("Cloudflare", "103.22.200.0/22"),
+    ("Cloudflare", "103.31.4.0/22"),
+    ("Cloudflare", "141.101.64.0/18"),
+    ("Cloudflare", "108.162.192.0/18"),
+    ("Cloudflare", "190.93.240.0/20"),
+    ("Cloudflare", "188.114.96.0/20"),
+    ("Cloudflare", "197.234.240.0/22"),
+    ("Cloudflare", "198.41.128.0/17"),
+    ("Cloudflare", "162.158.0.0/15"),
+    ("Cloudflare", "104.16.0.0/13"),
+    ("Cloudflare", "104.24.0.0/14"),
+    ("Cloudflare", "172.64.0.0/13"),
+    ("Cloudflare", "131.0.72.0/22"),
+    ("Fastly", "23.235.32.0/20"),
+    ("Fastly", "43.249.72.0/22"),
+    ("Fastly", "103.244.50.0/24"),
+    ("Fastly", "103.245.222.0/23"),
+    ("Fastly", "103.245.224.0/24"),
+    ("Fastly", "104.156.80.0/20"),
+    ("Fastly", "140.248.64.0/18"),
+    ("Fastly", "140.248.128.0/17"),
+    ("Fastly", "146.75.0.0/17"),
+    ("Fastly", "151.101.0.0/16"),
+    ("Fastly", "157.52.64.0/18"),
+    ("Fastly", "167.82.0.0/17"),
+    ("Fastly", "172.111.64.0/18"),
+    ("Fastly", "185.31.16.0/22"),
+    ("Fastly", "199.27.72.0/21"),
+    ("Fastly", "199.232.0.0/16"),
+    ("AWS CloudFront", "13.32.0.0/15"),
+    ("AWS CloudFront", "13.224.0.0/14"),
+    ("AWS CloudFront", "13.249.0.0/16"),
+    ("AWS CloudFront", "18.64.0.0/14"),
+    ("AWS CloudFront", "52.84.0.0/15"),
+    ("AWS CloudFront", "54.182.0.0/16"),
+    ("AWS CloudFront", "54.192.0.0/16"),
+    ("AWS CloudFront", "54.230.0.0/16"),
+    ("AWS CloudFront", "54.239.128.0/18"),
+    ("AWS CloudFront", "99.84.0.0/16"),
+    ("AWS CloudFront", "143.204.0.0/16"),
+    ("AWS CloudFront", "205.251.192.0/19"),
+    ("AWS CloudFront", "216.137.32.0/19"),
+];
+
+// the provider whose edge ip looks to be, if any
+pub fn cdn_provider(ip: IpAddr) -> Option<&'static str> {
+    CDN_RANGES
+        .iter()
+        .find(|&&(_, range)| range.parse::<Cidr>().is_ok_and(|range| range.contains(ip)))
+        .map(|&(provider, _)| provider)
+}
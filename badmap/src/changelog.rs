@@ -0,0 +1,84 @@
+// The running log of what changed between scans, for --changelog.
+// Each line is one port opening or closing on a target,
+//
+//     2026-10-14T09:30:00Z 192.168.1.10 8080 opened HTTP
+//
+// with the service name on the end only there to read. The last
+// state of every target is worked out by replaying the lines, so the
+// file is all the history there is and nothing else has to be kept
+use crate::diff::Baseline;
+use crate::output::Report;
+use crate::targets::normalize_ip;
+use badmap::get_service_by_port;
+
+use std::collections::{BTreeSet, HashMap};
+use std::io::{ErrorKind, Write};
+
+// which ports each target had open as of the last line about it
+fn replay(contents: &str) -> Result<HashMap<String, BTreeSet<u16>>, String> {
+    let mut state: HashMap<String, BTreeSet<u16>> = HashMap::new();
+
+    for (number, line) in contents.lines().enumerate() {
+        let line: &str = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (Some(target), Some(Ok(port)), Some(&change)) = (fields.get(1), fields.get(2).map(|port| port.parse::<u16>()), fields.get(3)) else {
+            return Err(format!("line {}: expected a timestamp, target, port, and opened or closed", number + 1));
+        };
+
+        let ports: &mut BTreeSet<u16> = state.entry(target.to_string()).or_default();
+        match change {
+            "opened" => ports.insert(port),
+            "closed" => ports.remove(&port),
+            _ => return Err(format!("line {}: expected opened or closed, not {}", number + 1, change)),
+        };
+    }
+
+    Ok(state)
+}
+
+// appends a line for every port that opened or closed on each report's
+// target since the last time the changelog saw it. A target the
+// changelog hasn't seen yet is compared with the baseline if there is
+// one, otherwise everything open on it is new. Only the ports the
+// report checked can have closed, so a partial scan doesn't log the
+// rest as closing. Gives back how many changes were logged
+pub fn append_changes(path: &str, reports: &[Report], baseline: Option<&[Baseline]>, timestamp: &str) -> Result<usize, String> {
+    let contents: String = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(format!("Failed to read changelog {}: {}", path, err)),
+    };
+    let mut state: HashMap<String, BTreeSet<u16>> = replay(&contents).map_err(|err| format!("Invalid changelog {}: {}", path, err))?;
+
+    let mut lines: Vec<String> = Vec::new();
+    for report in reports {
+        let target: String = normalize_ip(&report.target);
+        let previous: BTreeSet<u16> = match state.remove(&target) {
+            Some(previous) => previous,
+            None => baseline.and_then(|baseline| baseline.iter().find(|baseline| baseline.target == target)).map(|baseline| baseline.ports.iter().copied().collect()).unwrap_or_default(),
+        };
+        let open: BTreeSet<u16> = report.open_ports.iter().map(|result| result.port).collect();
+
+        let opened = open.difference(&previous).map(|&port| (port, "opened"));
+        let closed = previous.iter().filter(|port| !open.contains(port) && report.checked_ports.contains(port)).map(|&port| (port, "closed"));
+
+        let mut changes: Vec<(u16, &str)> = opened.chain(closed).collect();
+        changes.sort_unstable();
+        for (port, change) in changes {
+            lines.push(format!("{} {} {} {} {}", timestamp, target, port, change, get_service_by_port(port).unwrap_or("<unknown>")));
+        }
+    }
+
+    if lines.is_empty() {
+        return Ok(0);
+    }
+
+    let mut file = std::fs::File::options().create(true).append(true).open(path).map_err(|err| format!("Failed to open changelog {}: {}", path, err))?;
+    file.write_all(format!("{}\n", lines.join("\n")).as_bytes()).map_err(|err| format!("Failed to write changelog {}: {}", path, err))?;
+
+    Ok(lines.len())
+}
@@ -0,0 +1,183 @@
+// CIDR ranges like 10.0.0.0/8 or fe80::/10, used anywhere we need
+// to check whether an address falls inside a network
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    // always stored with the host bits cleared
+    network: IpAddr,
+    prefix: u8,
+}
+
+// the mask for the top prefix bits out of a total of bits
+fn mask(prefix: u8, bits: u8) -> u128 {
+    if prefix == 0 {
+        0
+    } else {
+        (u128::MAX << (bits - prefix)) & (u128::MAX >> (128 - bits))
+    }
+}
+
+impl Cidr {
+    pub fn new(network: IpAddr, prefix: u8) -> Result<Self, String> {
+        let bits: u8 = if network.is_ipv4() { 32 } else { 128 };
+        if prefix > bits {
+            return Err(format!("prefix /{} is too long for {}", prefix, network));
+        }
+
+        let network: IpAddr = match network {
+            IpAddr::V4(ip) => IpAddr::V4((u32::from(ip) & mask(prefix, 32) as u32).into()),
+            IpAddr::V6(ip) => IpAddr::V6((u128::from(ip) & mask(prefix, 128)).into()),
+        };
+
+        Ok(Cidr { network, prefix })
+    }
+
+    pub fn network(&self) -> IpAddr {
+        self.network
+    }
+
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    // every IPv4 address in the range that could be a host, which
+    // leaves out the network and broadcast addresses unless the
+    // range is too small to have them. IPv6 ranges have no hosts
+    // listed since they're far too big to walk
+    pub fn hosts(&self) -> Vec<Ipv4Addr> {
+        let IpAddr::V4(network) = self.network else {
+            return Vec::new();
+        };
+
+        let first: u32 = u32::from(network);
+        let last: u32 = first | !(mask(self.prefix, 32) as u32);
+
+        if self.prefix >= 31 {
+            (first..=last).map(Ipv4Addr::from).collect()
+        } else {
+            (first + 1..last).map(Ipv4Addr::from).collect()
+        }
+    }
+
+    // whether ip is inside this range, addresses of the other
+    // family are never inside it
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => u32::from(ip) & mask(self.prefix, 32) as u32 == u32::from(network),
+            (IpAddr::V6(network), IpAddr::V6(ip)) => u128::from(ip) & mask(self.prefix, 128) == u128::from(network),
+            _ => false,
+        }
+    }
+}
+
+// parses a.b.c.d/n, or a bare address as a range of just itself
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = match s.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (s, None),
+        };
+
+        let network: IpAddr = address.parse().map_err(|_| format!("invalid address in range: {}", s))?;
+        let prefix: u8 = match prefix {
+            Some(prefix) => prefix.parse().map_err(|_| format!("invalid prefix in range: {}", s))?,
+            None if network.is_ipv4() => 32,
+            None => 128,
+        };
+
+        Cidr::new(network, prefix)
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(range: &str) -> Cidr {
+        range.parse().unwrap()
+    }
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn clears_the_host_bits() {
+        assert_eq!(cidr("10.1.2.3/8").to_string(), "10.0.0.0/8");
+        assert_eq!(cidr("192.168.1.77/26").to_string(), "192.168.1.64/26");
+        assert_eq!(cidr("2001:db8::dead:beef/64").to_string(), "2001:db8::/64");
+        assert_eq!(cidr("10.1.2.3").to_string(), "10.1.2.3/32");
+        assert_eq!(cidr("2001:db8::1").to_string(), "2001:db8::1/128");
+    }
+
+    #[test]
+    fn contains_the_addresses_under_the_mask() {
+        let range: Cidr = cidr("172.16.0.0/12");
+        assert!(range.contains(ip("172.16.0.0")));
+        assert!(range.contains(ip("172.31.255.255")));
+        assert!(!range.contains(ip("172.32.0.0")));
+        assert!(!range.contains(ip("172.15.255.255")));
+
+        let range: Cidr = cidr("fe80::/10");
+        assert!(range.contains(ip("fe80::1")));
+        assert!(range.contains(ip("febf:ffff::1")));
+        assert!(!range.contains(ip("fec0::1")));
+    }
+
+    #[test]
+    fn zero_prefix_contains_its_whole_family() {
+        assert!(cidr("0.0.0.0/0").contains(ip("0.0.0.0")));
+        assert!(cidr("0.0.0.0/0").contains(ip("255.255.255.255")));
+        assert!(cidr("10.0.0.0/0").contains(ip("8.8.8.8")));
+        assert!(cidr("::/0").contains(ip("::")));
+        assert!(cidr("::/0").contains(ip("ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff")));
+    }
+
+    #[test]
+    fn full_prefix_contains_only_itself() {
+        let range: Cidr = cidr("10.0.0.1/32");
+        assert!(range.contains(ip("10.0.0.1")));
+        assert!(!range.contains(ip("10.0.0.0")));
+        assert!(!range.contains(ip("10.0.0.2")));
+
+        let range: Cidr = cidr("2001:db8::1/128");
+        assert!(range.contains(ip("2001:db8::1")));
+        assert!(!range.contains(ip("2001:db8::")));
+        assert!(!range.contains(ip("2001:db8::2")));
+    }
+
+    #[test]
+    fn never_contains_the_other_family() {
+        assert!(!cidr("0.0.0.0/0").contains(ip("::1")));
+        assert!(!cidr("::/0").contains(ip("127.0.0.1")));
+        assert!(!cidr("10.0.0.1").contains(ip("::ffff:10.0.0.1")));
+    }
+
+    #[test]
+    fn rejects_bad_ranges() {
+        assert_eq!("10.0.0.0/33".parse::<Cidr>().unwrap_err(), "prefix /33 is too long for 10.0.0.0");
+        assert_eq!("::/129".parse::<Cidr>().unwrap_err(), "prefix /129 is too long for ::");
+        assert_eq!("10.0.0.0/-1".parse::<Cidr>().unwrap_err(), "invalid prefix in range: 10.0.0.0/-1");
+        assert_eq!("10.0.0.0/".parse::<Cidr>().unwrap_err(), "invalid prefix in range: 10.0.0.0/");
+        assert_eq!("example.com/24".parse::<Cidr>().unwrap_err(), "invalid address in range: example.com/24");
+    }
+
+    #[test]
+    fn lists_the_hosts_of_ipv4_ranges() {
+        assert_eq!(cidr("10.0.0.0/30").hosts(), vec![Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2)]);
+        assert_eq!(cidr("10.0.0.0/31").hosts(), vec![Ipv4Addr::new(10, 0, 0, 0), Ipv4Addr::new(10, 0, 0, 1)]);
+        assert_eq!(cidr("10.0.0.9/32").hosts(), vec![Ipv4Addr::new(10, 0, 0, 9)]);
+        assert!(cidr("2001:db8::/120").hosts().is_empty());
+    }
+}
@@ -0,0 +1,187 @@
+// The optional config file for settings that are too fiddly to pass
+// as flags, and for profiles saved with --save-profile. It's read
+// from --config, or from badmap/config.toml under the user's config
+// directory if that exists
+use serde::Deserialize;
+use toml_edit::{value, Array, DocumentMut, Item, Table};
+
+use std::collections::HashMap;
+use std::env;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+// the config file as it's written, before the ports and error
+// names in it are checked
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    // replaces the built-in exposure weight of a port, toml keys
+    // are always strings so the ports get parsed after loading
+    exposure_weights: HashMap<String, u32>,
+
+    retry: RetryConfig,
+
+    // saved sets of flags, written by --save-profile
+    profiles: HashMap<String, ProfileConfig>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ProfileConfig {
+    // the flags the profile stands for, as they'd be typed
+    flags: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RetryConfig {
+    // replaces the built-in list of errors that get retried, by the
+    // names in ERROR_KIND_NAMES
+    retryable: Option<Vec<String>>,
+}
+
+// the errors that can be named in the config, using the same
+// snake_case names as the ErrorKind variants
+const ERROR_KIND_NAMES: &[(&str, ErrorKind)] = &[
+    ("timed_out", ErrorKind::TimedOut),
+    ("would_block", ErrorKind::WouldBlock),
+    ("interrupted", ErrorKind::Interrupted),
+    ("connection_refused", ErrorKind::ConnectionRefused),
+    ("connection_reset", ErrorKind::ConnectionReset),
+    ("connection_aborted", ErrorKind::ConnectionAborted),
+    ("not_connected", ErrorKind::NotConnected),
+    ("addr_in_use", ErrorKind::AddrInUse),
+    ("addr_not_available", ErrorKind::AddrNotAvailable),
+    ("network_unreachable", ErrorKind::NetworkUnreachable),
+    ("host_unreachable", ErrorKind::HostUnreachable),
+    ("network_down", ErrorKind::NetworkDown),
+    ("permission_denied", ErrorKind::PermissionDenied),
+    ("other", ErrorKind::Other),
+];
+
+// the settings from the config file, checked so nothing has to fail
+// partway through a scan
+#[derive(Debug, Default)]
+pub struct Config {
+    exposure_weights: HashMap<u16, u32>,
+    retryable: Option<Vec<ErrorKind>>,
+    profiles: HashMap<String, Vec<String>>,
+}
+
+impl Config {
+    // the exposure weight overrides keyed by port
+    pub fn exposure_weights(&self) -> HashMap<u16, u32> {
+        self.exposure_weights.clone()
+    }
+
+    // the errors that should be retried if the config overrides them
+    pub fn retryable(&self) -> Option<Vec<ErrorKind>> {
+        self.retryable.clone()
+    }
+
+    // the flags saved under the profile name, if there is one
+    pub fn profile(&self, name: &str) -> Option<Vec<String>> {
+        self.profiles.get(name).cloned()
+    }
+}
+
+impl TryFrom<ConfigFile> for Config {
+    type Error = String;
+
+    fn try_from(file: ConfigFile) -> Result<Self, Self::Error> {
+        let mut exposure_weights: HashMap<u16, u32> = HashMap::new();
+        for (port, &weight) in &file.exposure_weights {
+            let port: u16 = port.parse().map_err(|_| format!("Invalid port in config exposure_weights: {}", port))?;
+            exposure_weights.insert(port, weight);
+        }
+
+        let retryable: Option<Vec<ErrorKind>> = match &file.retry.retryable {
+            Some(names) => Some(
+                names
+                    .iter()
+                    .map(|name| match ERROR_KIND_NAMES.iter().find(|(known, _)| known == name) {
+                        Some(&(_, kind)) => Ok(kind),
+                        None => Err(format!("Unknown error kind in config retry.retryable: {}", name)),
+                    })
+                    .collect::<Result<Vec<ErrorKind>, String>>()?,
+            ),
+            None => None,
+        };
+
+        let profiles: HashMap<String, Vec<String>> = file.profiles.into_iter().map(|(name, profile)| (name, profile.flags)).collect();
+
+        Ok(Config { exposure_weights, retryable, profiles })
+    }
+}
+
+// $XDG_CONFIG_HOME/badmap/config.toml, falling back on ~/.config
+fn default_config_path() -> Option<PathBuf> {
+    let config_dir: PathBuf = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+
+    Some(config_dir.join("badmap").join("config.toml"))
+}
+
+// the config file that was asked for, or the default one
+fn config_path(path: Option<&str>) -> Option<PathBuf> {
+    match path {
+        Some(path) => Some(PathBuf::from(path)),
+        None => default_config_path(),
+    }
+}
+
+// saves flags as the profile name in the config file, replacing any
+// profile of the same name. Everything else in the file is kept as
+// it was written, comments included, and the file is created if it
+// doesn't exist yet
+pub fn save_profile(path: Option<&str>, name: &str, flags: &[String]) -> Result<PathBuf, String> {
+    let path: PathBuf = config_path(path).ok_or("No config directory to save the profile in, pass --config")?;
+
+    let contents: String = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(format!("Failed to read config file {}: {}", path.display(), err)),
+    };
+    let mut document: DocumentMut = contents.parse().map_err(|err| format!("Invalid config file {}: {}", path.display(), err))?;
+
+    let profiles = document.entry("profiles").or_insert_with(|| {
+        let mut profiles = Table::new();
+        profiles.set_implicit(true);
+        Item::Table(profiles)
+    });
+    let Some(profiles) = profiles.as_table_mut() else {
+        return Err(format!("Invalid config file {}: profiles has to be a table", path.display()));
+    };
+
+    let mut profile = Table::new();
+    profile.insert("flags", value(flags.iter().collect::<Array>()));
+    profiles.insert(name, Item::Table(profile));
+
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir).map_err(|err| format!("Failed to create {}: {}", dir.display(), err))?;
+    }
+    std::fs::write(&path, document.to_string()).map_err(|err| format!("Failed to write config file {}: {}", path.display(), err))?;
+
+    Ok(path)
+}
+
+// loads the config, a missing default config just means using the
+// defaults but a missing config that was asked for is an error
+pub fn load_config(path: Option<&str>) -> Result<Config, String> {
+    let explicit: bool = path.is_some();
+    let Some(path) = config_path(path) else {
+        return Ok(Config::default());
+    };
+
+    let contents: String = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == ErrorKind::NotFound && !explicit => return Ok(Config::default()),
+        Err(err) => return Err(format!("Failed to read config file {}: {}", path.display(), err)),
+    };
+
+    let file: ConfigFile = toml::from_str(&contents).map_err(|err| format!("Invalid config file {}: {}", path.display(), err))?;
+
+    Config::try_from(file)
+}
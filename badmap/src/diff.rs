@@ -0,0 +1,158 @@
+// `badmap diff`, comparing two json reports that were saved earlier
+// without scanning anything
+use crate::targets::normalize_ip;
+
+use serde::Deserialize;
+
+use std::collections::BTreeMap;
+use std::process::exit;
+
+#[derive(Deserialize)]
+struct SavedPort {
+    port: u16,
+    service: Option<String>,
+    banner: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SavedReport {
+    target: String,
+    #[serde(default)]
+    domain: Option<String>,
+    open_ports: Vec<SavedPort>,
+}
+
+// the open ports a saved report found on one target, what --monitor
+// checks are still open
+pub struct Baseline {
+    pub target: String,
+    pub domain: Option<String>,
+    pub ports: Vec<u16>,
+}
+
+// a json report is one object for a single target or an array of
+// them for a list of targets
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SavedReports {
+    One(SavedReport),
+    Many(Vec<SavedReport>),
+}
+
+// downloads a report kept on a server, like a baseline that a fleet
+// of scanners all compare against
+async fn fetch_report(url: &str) -> Result<String, reqwest::Error> {
+    reqwest::get(url).await?.error_for_status()?.text().await
+}
+
+// reads a report from a file, or from an http:// or https:// URL
+async fn load_reports(path: &str) -> Result<Vec<SavedReport>, String> {
+    let contents: String = if path.starts_with("http://") || path.starts_with("https://") {
+        fetch_report(path).await.map_err(|err| format!("Failed to read report {}: {}", path, err))?
+    } else {
+        tokio::fs::read_to_string(path).await.map_err(|err| format!("Failed to read report {}: {}", path, err))?
+    };
+
+    let mut reports: Vec<SavedReport> = match serde_json::from_str(&contents) {
+        Ok(SavedReports::One(report)) => vec![report],
+        Ok(SavedReports::Many(reports)) => reports,
+        Err(err) => return Err(format!("{} isn't a json report from BadMap: {}", path, err)),
+    };
+
+    // reports written by hand or by other tools can spell an IPv6
+    // address differently, which shouldn't make it a different target
+    for report in &mut reports {
+        report.target = normalize_ip(&report.target);
+    }
+
+    Ok(reports)
+}
+
+// the open ports of every target in the report at path, for --monitor
+pub async fn load_baseline(path: &str) -> Result<Vec<Baseline>, String> {
+    let reports: Vec<SavedReport> = load_reports(path).await?;
+
+    Ok(reports
+        .into_iter()
+        .map(|report| Baseline {
+            target: report.target,
+            domain: report.domain,
+            ports: report.open_ports.iter().map(|port| port.port).collect(),
+        })
+        .collect())
+}
+
+fn describe(port: &SavedPort) -> String {
+    let service: &str = port.service.as_deref().unwrap_or("<unknown>");
+
+    match &port.banner {
+        Some(banner) => format!("{}  open  {}  {}", port.port, service, banner),
+        None => format!("{}  open  {}", port.port, service),
+    }
+}
+
+// the lines for one target, + for newly open ports, - for ones that
+// closed, and ~ for ports whose service or banner changed
+fn diff_ports(old: &[SavedPort], new: &[SavedPort]) -> Vec<String> {
+    let old: BTreeMap<u16, &SavedPort> = old.iter().map(|port| (port.port, port)).collect();
+    let new: BTreeMap<u16, &SavedPort> = new.iter().map(|port| (port.port, port)).collect();
+
+    let mut ports: Vec<u16> = old.keys().chain(new.keys()).copied().collect();
+    ports.sort_unstable();
+    ports.dedup();
+
+    ports
+        .into_iter()
+        .filter_map(|port| match (old.get(&port), new.get(&port)) {
+            (None, Some(new)) => Some(format!("+ {}", describe(new))),
+            (Some(old), None) => Some(format!("- {}", describe(old))),
+            (Some(old), Some(new)) if old.service != new.service || old.banner != new.banner => {
+                Some(format!("~ {}  (was {})", describe(new), describe(old)))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+// prints what changed from the report at old_path to the one at
+// new_path, target by target
+pub async fn run_diff(old_path: &str, new_path: &str) {
+    let (old, new): (Vec<SavedReport>, Vec<SavedReport>) = match (load_reports(old_path).await, load_reports(new_path).await) {
+        (Ok(old), Ok(new)) => (old, new),
+        (Err(err), _) | (_, Err(err)) => {
+            eprintln!("{}", err);
+            exit(1);
+        }
+    };
+
+    // every target from either report, in the order they first show up
+    let mut targets: Vec<&str> = Vec::new();
+    for report in old.iter().chain(new.iter()) {
+        if !targets.contains(&report.target.as_str()) {
+            targets.push(&report.target);
+        }
+    }
+
+    println!("Comparing {} to {}", old_path, new_path);
+
+    let mut changed: bool = false;
+    for target in targets {
+        let old_ports: &[SavedPort] = old.iter().find(|report| report.target == target).map_or(&[], |report| &report.open_ports);
+        let new_ports: &[SavedPort] = new.iter().find(|report| report.target == target).map_or(&[], |report| &report.open_ports);
+
+        let lines: Vec<String> = diff_ports(old_ports, new_ports);
+        if lines.is_empty() {
+            continue;
+        }
+
+        changed = true;
+        println!("\n{}\n", target);
+        for line in lines {
+            println!("{}", line);
+        }
+    }
+
+    if !changed {
+        println!("\nNo changes");
+    }
+}
@@ -0,0 +1,441 @@
+// The expressions --filter takes to pick which open ports get
+// reported, like
+//
+//     port>1024 && service!=unknown && !(banner~"OpenSSH")
+//
+// Each comparison is a field, an operator, and a value. port and
+// latency (in milliseconds) are numbers and take ==, !=, <, <=, >,
+// and >=. state, service, and banner are text and take ==, !=, and ~
+// for contains, all ignoring case. A port counted as open after a
+// reset has the state "open|reset", which state~open matches too.
+// Comparisons can be joined with && and ||, negated with !, and
+// grouped with parentheses, with && binding tighter than ||
+use crate::output::get_service;
+use badmap::PortResult;
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Port,
+    Latency,
+    State,
+    Service,
+    Banner,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "port" => Some(Field::Port),
+            "latency" => Some(Field::Latency),
+            "state" => Some(Field::State),
+            "service" => Some(Field::Service),
+            "banner" => Some(Field::Banner),
+            _ => None,
+        }
+    }
+
+    fn is_number(self) -> bool {
+        matches!(self, Field::Port | Field::Latency)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    Number(f64),
+    Text(String),
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Field, Op, Value),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Op(Op),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+// what was wrong with a filter, with the column it went wrong at
+// counting from 1
+#[derive(Debug, Clone)]
+pub struct FilterError {
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "at column {}: {}", self.column, self.message)
+    }
+}
+
+impl std::error::Error for FilterError {}
+
+fn error(column: usize, message: impl Into<String>) -> FilterError {
+    FilterError { column, message: message.into() }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || "_.-/:".contains(c)
+}
+
+// splits the expression into tokens, each with the column it starts at
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, FilterError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens: Vec<(usize, Token)> = Vec::new();
+    let mut i: usize = 0;
+
+    while i < chars.len() {
+        let column: usize = i + 1;
+        let next: Option<char> = chars.get(i + 1).copied();
+
+        let (token, length): (Token, usize) = match (chars[i], next) {
+            (c, _) if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            ('&', Some('&')) => (Token::And, 2),
+            ('|', Some('|')) => (Token::Or, 2),
+            ('=', Some('=')) => (Token::Op(Op::Eq), 2),
+            ('!', Some('=')) => (Token::Op(Op::Ne), 2),
+            ('<', Some('=')) => (Token::Op(Op::Le), 2),
+            ('>', Some('=')) => (Token::Op(Op::Ge), 2),
+            ('<', _) => (Token::Op(Op::Lt), 1),
+            ('>', _) => (Token::Op(Op::Gt), 1),
+            ('~', _) => (Token::Op(Op::Contains), 1),
+            ('!', _) => (Token::Not, 1),
+            ('(', _) => (Token::Open, 1),
+            (')', _) => (Token::Close, 1),
+            (quote @ ('"' | '\''), _) => {
+                let Some(length) = chars[i + 1..].iter().position(|&c| c == quote) else {
+                    return Err(error(column, "unterminated string"));
+                };
+                (Token::Quoted(chars[i + 1..i + 1 + length].iter().collect()), length + 2)
+            }
+            (c, _) if is_word_char(c) => {
+                // a single | inside a word is part of it, so state==open|reset
+                // works without quotes, while || still ends the word
+                let mut length: usize = 1;
+                while let Some(&c) = chars.get(i + length) {
+                    if !(is_word_char(c) || (c == '|' && chars.get(i + length + 1).is_some_and(|&next| is_word_char(next)))) {
+                        break;
+                    }
+                    length += 1;
+                }
+                (Token::Word(chars[i..i + length].iter().collect()), length)
+            }
+            (c, _) => return Err(error(column, format!("unexpected {}", c))),
+        };
+
+        tokens.push((column, token));
+        i += length;
+    }
+
+    Ok(tokens)
+}
+
+// a recursive descent parser over the tokens, one function per level
+// of precedence
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    position: usize,
+
+    // the column just past the end, for errors about something missing
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(_, token)| token)
+    }
+
+    fn column(&self) -> usize {
+        self.tokens.get(self.position).map_or(self.end, |&(column, _)| column)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).map(|(_, token)| token.clone());
+        self.position += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Expr, FilterError> {
+        let mut expr: Expr = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, FilterError> {
+        let mut expr: Expr = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, FilterError> {
+        match self.peek() {
+            Some(Token::Not) => {
+                self.next();
+                Ok(Expr::Not(Box::new(self.unary()?)))
+            }
+            Some(Token::Open) => {
+                self.next();
+                let expr: Expr = self.or()?;
+                if self.peek() != Some(&Token::Close) {
+                    return Err(error(self.column(), "expected )"));
+                }
+                self.next();
+                Ok(expr)
+            }
+            _ => self.compare(),
+        }
+    }
+
+    fn compare(&mut self) -> Result<Expr, FilterError> {
+        let column: usize = self.column();
+        let field: Field = match self.next() {
+            Some(Token::Word(name)) => Field::parse(&name).ok_or_else(|| error(column, format!("unknown field {}, expected port, latency, state, service, or banner", name)))?,
+            _ => return Err(error(column, "expected a field like port or state")),
+        };
+
+        let column: usize = self.column();
+        let op: Op = match self.next() {
+            Some(Token::Op(op)) => op,
+            _ => return Err(error(column, "expected an operator like == or >")),
+        };
+        if field.is_number() && op == Op::Contains {
+            return Err(error(column, "~ only works on state, service, and banner"));
+        }
+        if !field.is_number() && !matches!(op, Op::Eq | Op::Ne | Op::Contains) {
+            return Err(error(column, "state, service, and banner can only be compared with ==, !=, and ~"));
+        }
+
+        let column: usize = self.column();
+        let text: String = match self.next() {
+            Some(Token::Word(text) | Token::Quoted(text)) => text,
+            _ => return Err(error(column, "expected a value to compare with")),
+        };
+
+        let value: Value = if field.is_number() {
+            Value::Number(text.parse().map_err(|_| error(column, format!("expected a number, not {}", text)))?)
+        } else {
+            if field == Field::State && op != Op::Contains && !["open", "open|reset", "closed", "filtered"].contains(&text.to_ascii_lowercase().as_str()) {
+                return Err(error(column, format!("unknown state {}, expected open, open|reset, closed, or filtered", text)));
+            }
+            Value::Text(text.to_ascii_lowercase())
+        };
+
+        Ok(Expr::Compare(field, op, value))
+    }
+}
+
+// a parsed --filter expression, see the top of the file for the syntax
+#[derive(Debug, Clone)]
+pub struct Filter {
+    expr: Expr,
+}
+
+impl Filter {
+    pub fn parse(text: &str) -> Result<Self, FilterError> {
+        let tokens: Vec<(usize, Token)> = tokenize(text)?;
+        let mut parser = Parser { tokens, position: 0, end: text.chars().count() + 1 };
+
+        let expr: Expr = parser.or()?;
+        if parser.position < parser.tokens.len() {
+            return Err(error(parser.column(), "expected && or || between comparisons"));
+        }
+
+        Ok(Filter { expr })
+    }
+
+    // whether result passes the filter, banners being whether the
+    // service gets named from the banner as well as the port
+    pub fn matches(&self, result: &PortResult, banners: bool) -> bool {
+        evaluate(&self.expr, result, banners)
+    }
+}
+
+fn evaluate(expr: &Expr, result: &PortResult, banners: bool) -> bool {
+    match expr {
+        Expr::And(left, right) => evaluate(left, result, banners) && evaluate(right, result, banners),
+        Expr::Or(left, right) => evaluate(left, result, banners) || evaluate(right, result, banners),
+        Expr::Not(expr) => !evaluate(expr, result, banners),
+        Expr::Compare(field, op, value) => compare(*field, *op, value, result, banners),
+    }
+}
+
+fn compare(field: Field, op: Op, value: &Value, result: &PortResult, banners: bool) -> bool {
+    match value {
+        Value::Number(expected) => {
+            let actual: Option<f64> = match field {
+                Field::Port => Some(f64::from(result.port)),
+                // a port that never answered has no latency to compare
+                _ => result.latency.map(|latency| latency.as_secs_f64() * 1000.0),
+            };
+            let Some(actual) = actual else {
+                return false;
+            };
+
+            match op {
+                Op::Eq => actual == *expected,
+                Op::Ne => actual != *expected,
+                Op::Lt => actual < *expected,
+                Op::Le => actual <= *expected,
+                Op::Gt => actual > *expected,
+                Op::Ge => actual >= *expected,
+                Op::Contains => false,
+            }
+        }
+        Value::Text(expected) => {
+            let actual: String = match field {
+                Field::State => result.state().to_string(),
+                Field::Service => get_service(result, banners).map_or("unknown", |(name, _)| name).to_ascii_lowercase(),
+                _ => result.banner.as_deref().unwrap_or("").to_ascii_lowercase(),
+            };
+
+            match op {
+                Op::Ne => actual != *expected,
+                Op::Contains => actual.contains(expected.as_str()),
+                _ => actual == *expected,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::ErrorKind;
+    use std::time::Duration;
+
+    fn open(port: u16, banner: Option<&str>) -> PortResult {
+        PortResult { port, error: None, banner: banner.map(str::to_string), latency: Some(Duration::from_millis(5)), reset: false }
+    }
+
+    fn matches(filter: &str, result: &PortResult) -> bool {
+        Filter::parse(filter).unwrap().matches(result, true)
+    }
+
+    fn parse_error(filter: &str) -> FilterError {
+        Filter::parse(filter).unwrap_err()
+    }
+
+    #[test]
+    fn compares_numbers() {
+        let result: PortResult = open(8080, None);
+        assert!(matches("port==8080", &result));
+        assert!(matches("port>1024", &result));
+        assert!(!matches("port<=1024", &result));
+        assert!(matches("latency<10", &result));
+        assert!(!matches("latency>=10", &result));
+    }
+
+    #[test]
+    fn latency_without_an_answer_never_matches() {
+        let result = PortResult { latency: None, ..open(22, None) };
+        assert!(!matches("latency<10", &result));
+        assert!(!matches("latency>=10", &result));
+    }
+
+    #[test]
+    fn compares_text_ignoring_case() {
+        let result: PortResult = open(22, Some("SSH-2.0-OpenSSH_9.6"));
+        assert!(matches("service==ssh", &result));
+        assert!(matches("banner~openssh", &result));
+        assert!(matches("banner~\"OpenSSH_9\"", &result));
+        assert!(!matches("service!=SSH", &result));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let result: PortResult = open(80, None);
+        assert!(matches("port==22 && port==23 || port==80", &result));
+        assert!(matches("port==80 || port==22 && port==23", &result));
+        assert!(!matches("(port==80 || port==22) && port==23", &result));
+    }
+
+    #[test]
+    fn negates_and_groups() {
+        let result: PortResult = open(80, None);
+        assert!(!matches("!(port==80)", &result));
+        assert!(matches("!port==22", &result));
+        assert!(matches("!(port==22 || port==23) && !!port==80", &result));
+    }
+
+    #[test]
+    fn state_open_reset_needs_no_quotes() {
+        let reset = PortResult { reset: true, ..open(443, None) };
+        assert!(matches("state==open|reset", &reset));
+        assert!(matches("state=='open|reset'", &reset));
+        assert!(!matches("state==open|reset", &open(443, None)));
+        assert!(matches("state==open|reset||port==443", &open(443, None)));
+
+        let closed = PortResult { error: Some(ErrorKind::ConnectionRefused), latency: None, ..open(25, None) };
+        assert!(matches("state==closed", &closed));
+        assert!(matches("state~open", &reset));
+    }
+
+    #[test]
+    fn reports_the_column_of_the_error() {
+        let err: FilterError = parse_error("port>1024 && nope==1");
+        assert_eq!(err.column, 14);
+        assert!(err.message.contains("unknown field nope"));
+
+        let err: FilterError = parse_error("(port==80");
+        assert_eq!(err.column, 10);
+        assert_eq!(err.message, "expected )");
+
+        let err: FilterError = parse_error("port==80 port==81");
+        assert_eq!(err.column, 10);
+
+        let err: FilterError = parse_error("banner~\"open");
+        assert_eq!(err.column, 8);
+        assert_eq!(err.message, "unterminated string");
+
+        let err: FilterError = parse_error("port == 80 | port == 81");
+        assert_eq!(err.column, 12);
+        assert_eq!(err.message, "unexpected |");
+    }
+
+    #[test]
+    fn rejects_unknown_states_and_bad_operators() {
+        let err: FilterError = parse_error("state==opened");
+        assert_eq!(err.column, 8);
+        assert!(err.message.contains("unknown state opened"));
+
+        assert!(parse_error("port~80").message.contains("~ only works"));
+        assert!(parse_error("service>ssh").message.contains("can only be compared"));
+        assert!(parse_error("port==http").message.contains("expected a number"));
+        assert!(parse_error("port==").message.contains("expected a value"));
+        assert_eq!(parse_error("").column, 1);
+    }
+}
@@ -0,0 +1,151 @@
+// Picking the product and version out of a banner, with patterns in
+// the same spirit as nmap's service probes. Each pattern looks like
+//
+//     match ssh m|^SSH-[\d.]+-OpenSSH_([\w.]+)| p/OpenSSH/ v/$1/
+//
+// where the m, p, and v fields can use any delimiter, and $1 and up
+// in the product or version are filled in from the regex's groups
+use regex::Regex;
+
+// the patterns BadMap ships with, only for services that send a
+// banner before we send anything since that's all we read
+static BUILTIN_FINGERPRINTS: &str = r"
+match SSH m|^SSH-[\d.]+-OpenSSH_([\d.]+\w*)| p/OpenSSH/ v/$1/
+match SSH m|^SSH-[\d.]+-dropbear_([\w.]+)| p/Dropbear sshd/ v/$1/
+match SSH m|^SSH-[\d.]+-libssh[_-]([\w.]+)| p/libssh/ v/$1/
+match SSH m|^SSH-[\d.]+-Cisco-([\w.]+)| p/Cisco SSH/ v/$1/
+match FTP m|^220 \(vsFTPd ([\w.]+)\)| p/vsftpd/ v/$1/
+match FTP m|^220 ProFTPD ([\w.]+)| p/ProFTPD/ v/$1/
+match FTP m|^220.*Pure-FTPd| p/Pure-FTPd/
+match FTP m|^220.*FileZilla Server ([\w.]+)| p/FileZilla ftpd/ v/$1/
+match SMTP m|^220 [\w.-]+ ESMTP Postfix| p/Postfix smtpd/
+match SMTP m|^220 [\w.-]+ ESMTP Exim ([\w.]+)| p/Exim smtpd/ v/$1/
+match SMTP m|^220 [\w.-]+ ESMTP Sendmail ([\w.]+)| p/Sendmail/ v/$1/
+match SMTP m|^220 [\w.-]+ Microsoft ESMTP MAIL Service| p/Microsoft Exchange smtpd/
+match POP3 m|^\+OK Dovecot| p/Dovecot pop3d/
+match IMAP m|^\* OK .*Dovecot| p/Dovecot imapd/
+match IMAP m|^\* OK .*Cyrus IMAP.* v([\w.-]+)| p/Cyrus imapd/ v/$1/
+match VNC m|^RFB 0*(\d+)\.0*(\d+)| p/VNC/ v/protocol $1.$2/
+";
+
+// what a banner says about the service behind it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    pub service: String,
+    pub product: Option<String>,
+    pub version: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Pattern {
+    service: String,
+    regex: Regex,
+    product: Option<String>,
+    version: Option<String>,
+}
+
+// a list of patterns, tried in order with the first match winning
+#[derive(Debug, Clone)]
+pub struct FingerprintDb {
+    patterns: Vec<Pattern>,
+}
+
+// splits a field like m|...| off the front of rest into its name,
+// value, and whatever comes after it
+fn take_field(rest: &str) -> Option<(char, &str, &str)> {
+    let mut chars = rest.chars();
+    let name: char = chars.next()?;
+    let delimiter: char = chars.next()?;
+
+    let value_start: usize = name.len_utf8() + delimiter.len_utf8();
+    let value_len: usize = rest[value_start..].find(delimiter)?;
+    let after: &str = &rest[value_start + value_len + delimiter.len_utf8()..];
+
+    Some((name, &rest[value_start..value_start + value_len], after))
+}
+
+// parses one match line, see the top of the file for the layout
+fn parse_pattern(line: &str) -> Result<Pattern, String> {
+    let rest: &str = line.strip_prefix("match ").ok_or("expected the line to start with match")?;
+    let (service, rest) = rest.trim_start().split_once(' ').ok_or("expected a service and then m/regex/")?;
+
+    let (name, regex, mut rest) = take_field(rest.trim_start()).ok_or("expected m/regex/ after the service")?;
+    if name != 'm' {
+        return Err("expected m/regex/ after the service".to_string());
+    }
+
+    // nmap puts flags right after the regex, i for case insensitive
+    // being the one that matters here
+    let mut insensitive: bool = false;
+    while let Some(after) = rest.strip_prefix(['i', 's']) {
+        insensitive |= rest.starts_with('i');
+        rest = after;
+    }
+    let regex: Regex = Regex::new(&if insensitive { format!("(?i){}", regex) } else { regex.to_string() }).map_err(|err| err.to_string())?;
+
+    let mut pattern = Pattern { service: service.to_string(), regex, product: None, version: None };
+    while !rest.trim().is_empty() {
+        let (name, value, after) = take_field(rest.trim_start()).ok_or("expected p/product/ or v/version/")?;
+        match name {
+            'p' => pattern.product = Some(value.to_string()),
+            'v' => pattern.version = Some(value.to_string()),
+            // the other nmap fields like i// and cpe:// aren't used
+            _ => {}
+        }
+        rest = after;
+    }
+
+    Ok(pattern)
+}
+
+// fills $1 and up in template from the groups the regex captured
+fn expand(template: &str, captures: &regex::Captures) -> String {
+    let mut expanded: String = template.to_string();
+    for group in (1..captures.len()).rev() {
+        expanded = expanded.replace(&format!("${}", group), captures.get(group).map_or("", |value| value.as_str()));
+    }
+    expanded
+}
+
+impl FingerprintDb {
+    // the patterns BadMap ships with
+    pub fn builtin() -> Self {
+        FingerprintDb::parse(BUILTIN_FINGERPRINTS).unwrap()
+    }
+
+    // parses a fingerprint file, one match line per pattern, with
+    // blank lines and lines starting with # ignored
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut patterns: Vec<Pattern> = Vec::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line: &str = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            patterns.push(parse_pattern(line).map_err(|err| format!("line {}: {}", number + 1, err))?);
+        }
+
+        Ok(FingerprintDb { patterns })
+    }
+
+    // adds other's patterns after these, so they're only tried once
+    // none of these match
+    pub fn extend(&mut self, other: FingerprintDb) {
+        self.patterns.extend(other.patterns);
+    }
+
+    // the first pattern that matches banner, if any do
+    pub fn identify(&self, banner: &str) -> Option<Fingerprint> {
+        self.patterns.iter().find_map(|pattern| {
+            let captures = pattern.regex.captures(banner)?;
+
+            Some(Fingerprint {
+                service: pattern.service.clone(),
+                product: pattern.product.as_ref().map(|product| expand(product, &captures)),
+                version: pattern.version.as_ref().map(|version| expand(version, &captures)),
+            })
+        })
+    }
+}
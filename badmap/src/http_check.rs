@@ -0,0 +1,95 @@
+// Asking the web services on open ports for a path, for --http-check,
+// so a scan can also say whether a service is healthy and not just
+// whether its port is open
+use badmap::target_address;
+use futures::future;
+use reqwest::header::LOCATION;
+use reqwest::redirect::Policy;
+use reqwest::Client;
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+// what an open port answered with
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    pub status: u16,
+
+    // where a redirect points, None if the answer wasn't one
+    pub location: Option<String>,
+
+    // whether this is plain HTTP redirecting to an https:// URL,
+    // which means the real service is the HTTPS one
+    pub redirects_to_https: bool,
+}
+
+// what asking an open port for the path gave back, the response or
+// why there wasn't one
+pub type HttpCheck = Result<HttpResponse, String>;
+
+// picks the status and any redirect out of a response to a request
+// that was made over https or not
+fn http_response(response: &reqwest::Response, https: bool) -> HttpResponse {
+    let status = response.status();
+    let location: Option<String> = if status.is_redirection() { response.headers().get(LOCATION).and_then(|location| location.to_str().ok()).map(|location| location.to_string()) } else { None };
+    let redirects_to_https: bool = !https && location.as_deref().is_some_and(|location| location.get(..8).is_some_and(|scheme| scheme.eq_ignore_ascii_case("https://")));
+
+    HttpResponse { status: status.as_u16(), location, redirects_to_https }
+}
+
+// the url for path on port, with the domain in it if the target came
+// from one so the request gets the right Host header and SNI
+fn check_url(address: SocketAddr, domain: Option<&str>, https: bool, path: &str) -> String {
+    let scheme: &str = if https { "https" } else { "http" };
+    let host: String = match (domain, address.ip()) {
+        (Some(domain), _) => domain.to_string(),
+        (None, ip) if ip.is_ipv6() => format!("[{}]", ip),
+        (None, ip) => ip.to_string(),
+    };
+
+    format!("{}://{}:{}{}", scheme, host, address.port(), path)
+}
+
+// why a request failed, reqwest's own message repeats the whole url
+// so the innermost cause is what's worth showing
+fn describe(err: reqwest::Error) -> String {
+    if err.is_timeout() {
+        return "timed out".to_string();
+    }
+
+    let mut cause: &dyn std::error::Error = &err;
+    while let Some(source) = cause.source() {
+        cause = source;
+    }
+    cause.to_string()
+}
+
+// sends a GET for path to every port in ports, the bool saying which
+// of them are HTTPS. Redirects aren't followed, a redirect is its own
+// answer and where it points is kept, and certificates aren't checked
+// since internal services tend to have self-signed ones and only the
+// status is wanted
+pub async fn check_http(ip: &str, domain: Option<&str>, ports: &[(u16, bool)], path: &str, timeout: Duration) -> HashMap<u16, HttpCheck> {
+    let mut builder = Client::builder().timeout(timeout).redirect(Policy::none()).tls_danger_accept_invalid_certs(true);
+
+    // the domain has to connect to the address that was scanned, not
+    // whatever it resolves to now. The port in the url is what gets
+    // used, so one address covers every port
+    let addresses: Vec<(u16, bool, SocketAddr)> = ports.iter().filter_map(|&(port, https)| Some((port, https, target_address(ip, port).ok()?))).collect();
+    if let (Some(domain), Some(&(_, _, address))) = (domain, addresses.first()) {
+        builder = builder.resolve(domain, address);
+    }
+
+    let client: Client = match builder.build() {
+        Ok(client) => client,
+        Err(err) => return ports.iter().map(|&(port, _)| (port, Err(err.to_string()))).collect(),
+    };
+
+    let checks = addresses.iter().map(|&(port, https, address)| {
+        let request = client.get(check_url(address, domain, https, path)).send();
+        async move { (port, request.await.map(|response| http_response(&response, https)).map_err(describe)) }
+    });
+
+    future::join_all(checks).await.into_iter().collect()
+}
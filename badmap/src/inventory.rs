@@ -0,0 +1,80 @@
+// Exporting what was found for other tools to pick up, for
+// --export-inventory. Only open ports with a service BadMap recognized
+// are exported, since a bare port number isn't much use to anything
+// that wants to configure the service behind it
+use crate::output::{get_service, Report};
+
+use clap::ValueEnum;
+
+use std::collections::{BTreeMap, HashSet};
+
+// the layouts --inventory-format can write
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum InventoryFormat {
+    // one address and service per line, like
+    //
+    //     192.168.1.10 ssh
+    Hosts,
+
+    // an Ansible INI inventory with a group for each service, like
+    //
+    //     [ssh]
+    //     192.168.1.10 ansible_port=22
+    Ansible,
+}
+
+// a service name as something every tool will take as a name, so
+// lowercase with anything but letters and digits turned into _
+fn inventory_name(service: &str) -> String {
+    service.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' }).collect()
+}
+
+// every recognized open port in the reports, as the report it's from,
+// the port, and the service's inventory name
+fn services(reports: &[Report]) -> Vec<(&Report, u16, String)> {
+    reports
+        .iter()
+        .flat_map(|report| report.open_ports.iter().filter_map(move |result| get_service(result, report.banners).map(|(name, _)| (report, result.port, inventory_name(name)))))
+        .collect()
+}
+
+// a line for each service on each address, once even if the service
+// is on more than one port or the address was scanned more than once.
+// Gives back the inventory and how many lines are in it
+fn render_hosts(reports: &[Report]) -> (String, usize) {
+    let mut seen: HashSet<String> = HashSet::new();
+    let lines: Vec<String> = services(reports).into_iter().map(|(report, _, service)| format!("{} {}\n", report.target, service)).filter(|line| seen.insert(line.clone())).collect();
+    (lines.concat(), lines.len())
+}
+
+// a group for each service in name order, with its hosts in the order
+// they were scanned. A host that came from a domain goes by the domain
+// with the address it was scanned at as ansible_host. Gives back the
+// inventory and how many hosts are in it across every group
+fn render_ansible(reports: &[Report]) -> (String, usize) {
+    let services: Vec<(&Report, u16, String)> = services(reports);
+    let exported: usize = services.len();
+
+    let mut groups: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (report, port, service) in services {
+        let host: String = match &report.domain {
+            Some(domain) => format!("{} ansible_host={} ansible_port={}", domain, report.target, port),
+            None => format!("{} ansible_port={}", report.target, port),
+        };
+        groups.entry(service).or_default().push(host);
+    }
+
+    (groups.into_iter().map(|(service, hosts)| format!("[{}]\n{}\n", service, hosts.join("\n"))).collect::<Vec<String>>().join("\n"), exported)
+}
+
+// writes the inventory for reports to path in format, replacing
+// whatever was there. Gives back how many services were written
+pub fn write_inventory(path: &str, reports: &[Report], format: InventoryFormat) -> Result<usize, String> {
+    let (contents, exported): (String, usize) = match format {
+        InventoryFormat::Hosts => render_hosts(reports),
+        InventoryFormat::Ansible => render_ansible(reports),
+    };
+
+    std::fs::write(path, contents).map_err(|err| format!("Failed to write inventory {}: {}", path, err))?;
+    Ok(exported)
+}
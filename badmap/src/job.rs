@@ -0,0 +1,83 @@
+// Job files for --job, which describe a whole scan (targets, ports,
+// options, and where the report goes) in YAML or JSON so it can be
+// kept in version control and run again the same way
+use crate::output::Format;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use serde_yaml::Value;
+
+use std::collections::BTreeMap;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Job {
+    // IP addresses and hostnames, the same as the lines of a
+    // targets file
+    pub targets: Vec<String>,
+
+    // the exact ports to scan, instead of a profile or every port
+    pub ports: Option<Vec<u16>>,
+
+    pub profile: Option<String>,
+
+    // the same as -o and -f
+    pub output: Option<String>,
+    pub format: Option<Format>,
+
+    // any other flag by its long name, like banners: true or
+    // retries: 2, a flag given on the command line wins
+    pub options: BTreeMap<String, Value>,
+}
+
+// maps a job value back onto the command line flag it stands for,
+// false leaves a switch off and a list repeats the flag
+fn push_option(args: &mut Vec<String>, name: &str, value: &Value) -> Result<(), String> {
+    let flag = format!("--{}", name.replace('_', "-"));
+
+    match value {
+        Value::Bool(true) => args.push(flag),
+        Value::Bool(false) | Value::Null => {}
+        Value::Number(number) => args.extend([flag, number.to_string()]),
+        Value::String(string) => args.extend([flag, string.clone()]),
+        Value::Sequence(values) => {
+            for value in values {
+                push_option(args, name, value)?;
+            }
+        }
+        _ => return Err(format!("Unsupported value for {} in job file", name)),
+    }
+
+    Ok(())
+}
+
+impl Job {
+    // the flags the job stands for, everything but the targets and
+    // ports which don't have a flag of their own
+    pub fn to_args(&self) -> Result<Vec<String>, String> {
+        let mut args: Vec<String> = Vec::new();
+
+        if let Some(profile) = &self.profile {
+            args.extend(["--profile".to_string(), profile.clone()]);
+        }
+        if let Some(output) = &self.output {
+            args.extend(["--output-filename".to_string(), output.clone()]);
+        }
+        if let Some(format) = self.format {
+            args.extend(["--format".to_string(), format.to_possible_value().unwrap().get_name().to_string()]);
+        }
+        for (name, value) in &self.options {
+            push_option(&mut args, name, value)?;
+        }
+
+        Ok(args)
+    }
+}
+
+// loads a job file, YAML is a superset of JSON so the same parser
+// reads both
+pub fn load_job(path: &str) -> Result<Job, String> {
+    let contents: String = std::fs::read_to_string(path).map_err(|err| format!("Failed to read job file {}: {}", path, err))?;
+
+    serde_yaml::from_str(&contents).map_err(|err| format!("Invalid job file {}: {}", path, err))
+}
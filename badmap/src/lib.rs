@@ -0,0 +1,921 @@
+// BadMap's scanning engine, the badmap binary is a thin CLI on top
+// of this so it can also be embedded in other programs
+use futures::{future, stream, FutureExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpSocket, TcpStream};
+use tokio::sync::Semaphore;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// re-exported so callers don't need to depend on tokio-util
+// just to cancel a scan
+pub use tokio_util::sync::CancellationToken;
+
+// CIDR ranges and the allow/deny scope policy built on them
+pub mod cidr;
+pub mod scope;
+pub use cidr::Cidr;
+pub use scope::ScopePolicy;
+
+// spotting addresses that are a CDN's edge instead of the origin
+pub mod cdn;
+pub use cdn::cdn_provider;
+
+// scanning through an SSH bastion instead of connecting directly
+pub mod ssh;
+pub use ssh::SshJump;
+
+// what connections are opened with, so a scan can be run without
+// the network
+pub mod transport;
+pub use transport::{Connection, MockPort, MockTransport, TcpTransport, Transport};
+
+// how a scan is run, built with ScanOptions::builder()
+pub mod options;
+pub use options::{ScanOptions, ScanOptionsBuilder, DEFAULT_RETRYABLE, TIMEOUT_SCALING_SAMPLES};
+
+// host discovery over a whole range without any port scanning
+pub mod sweep;
+pub use sweep::{sweep, LiveHost, SweepMethod, SweepResult};
+
+// the built-in port and banner knowledge used to name services
+pub mod services;
+pub use services::{exposure_score, get_category, get_service_by_port, identify_service, service_for_port, services, Category, Confidence, Protocol, ServiceMatch, SERVICES};
+
+// product and version detection from banners
+pub mod fingerprint;
+pub use fingerprint::{Fingerprint, FingerprintDb};
+
+// the result of scanning a single port
+#[derive(Debug, Clone)]
+pub struct PortResult {
+    pub port: u16,
+
+    // None if the port is open, otherwise why connecting failed
+    pub error: Option<ErrorKind>,
+
+    // the first line the service sent, only grabbed when banners
+    // are turned on
+    pub banner: Option<String>,
+
+    // how long the connect took to get an answer, None if it
+    // never got one before the timeout
+    pub latency: Option<Duration>,
+
+    // whether the connection was accepted and then reset before
+    // anything came back, only looked for with ScanOptions::reset_as_open
+    pub reset: bool,
+}
+
+// what a port looked like from the outside, closed means the host
+// answered with a refusal and filtered means something got in the
+// way of an answer. OpenReset is an open port whose connection was
+// reset right after it was accepted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortState {
+    Open,
+    OpenReset,
+    Closed,
+    Filtered,
+}
+
+impl fmt::Display for PortState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PortState::Open => write!(f, "open"),
+            PortState::OpenReset => write!(f, "open|reset"),
+            PortState::Closed => write!(f, "closed"),
+            PortState::Filtered => write!(f, "filtered"),
+        }
+    }
+}
+
+impl PortResult {
+    pub fn is_open(&self) -> bool {
+        self.error.is_none()
+    }
+
+    pub fn state(&self) -> PortState {
+        match self.error {
+            None if self.reset => PortState::OpenReset,
+            None => PortState::Open,
+            Some(ErrorKind::ConnectionRefused) => PortState::Closed,
+            Some(_) => PortState::Filtered,
+        }
+    }
+}
+
+// everything about a finished scan of one target, so callers don't
+// have to work the totals out from the results themselves
+#[derive(Debug, Clone)]
+pub struct ScanReport {
+    // the address that was scanned
+    pub target: String,
+
+    // every port that was scanned, in the order they were given
+    pub results: Vec<PortResult>,
+
+    // how long the whole scan took, warmup included
+    pub duration: Duration,
+
+    pub open: usize,
+    pub closed: usize,
+    pub filtered: usize,
+
+    // how many ports failed with each kind of error
+    pub errors: HashMap<ErrorKind, usize>,
+
+    // whether the scan was cancelled before every port was scanned
+    pub cancelled: bool,
+
+    // the most ports that were really being scanned at the same time
+    pub peak_concurrency: usize,
+
+    // how many retries were made across every port
+    pub retries: usize,
+
+    // the connect timeout timeout scaling settled on, None if it was
+    // off or not enough connects answered to pick one
+    pub scaled_timeout: Option<Duration>,
+
+    // whether the target was given up on after too many timeouts in
+    // a row, see ScanOptionsBuilder::abort_after_timeouts
+    pub aborted: bool,
+
+    // the ports that never got scanned because the scan was cancelled
+    // or aborted, or because there was never a file descriptor to
+    // scan them with
+    pub untested: Vec<u16>,
+
+    // how many ports had to wait for a file descriptor because the
+    // system ran out, which means the concurrency is too high for it
+    pub fd_waits: usize,
+
+    // the ports out of untested that waited for a file descriptor the
+    // whole time and were never scanned
+    pub fd_exhausted: Vec<u16>,
+}
+
+impl ScanReport {
+    fn new(target: &str, scanned: Scanned, duration: Duration, cancelled: bool) -> Self {
+        let Scanned { results, peak_concurrency, retries, scaled_timeout, aborted, untested, fd_waits, fd_exhausted } = scanned;
+        let count = |state: PortState| results.iter().filter(|result| result.state() == state).count();
+        let (open, closed, filtered) = (count(PortState::Open) + count(PortState::OpenReset), count(PortState::Closed), count(PortState::Filtered));
+
+        let mut errors: HashMap<ErrorKind, usize> = HashMap::new();
+        for kind in results.iter().filter_map(|result| result.error) {
+            *errors.entry(kind).or_insert(0) += 1;
+        }
+
+        ScanReport { target: target.to_string(), results, duration, open, closed, filtered, errors, cancelled, peak_concurrency, retries, scaled_timeout, aborted, untested, fd_waits, fd_exhausted }
+    }
+
+    pub fn open_ports(&self) -> impl Iterator<Item = &PortResult> {
+        self.results.iter().filter(|result| result.is_open())
+    }
+}
+
+// how many throwaway connects the warmup does
+const WARMUP_CONNECTS: usize = 3;
+
+// the most we'll read from a service when grabbing its banner
+const BANNER_SIZE: usize = 512;
+
+// the interface index for an IPv6 zone, which can be given as the
+// index itself or as the interface's name
+fn zone_index(zone: &str) -> Option<u32> {
+    if let Ok(index) = zone.parse() {
+        return Some(index);
+    }
+
+    if_addrs::get_if_addrs().ok()?.into_iter().find(|interface| interface.name == zone)?.index
+}
+
+// Turns a target and port into the address to connect to. Targets are
+// an IP address, or for IPv6 link-local addresses an IP address with
+// a %zone naming the interface it's on like fe80::1%eth0, which plain
+// IpAddr parsing doesn't accept
+pub fn target_address(target: &str, port: u16) -> io::Result<SocketAddr> {
+    let (ip, zone): (&str, Option<&str>) = match target.split_once('%') {
+        Some((ip, zone)) => (ip, Some(zone)),
+        None => (target, None),
+    };
+
+    let ip: IpAddr = ip.parse().map_err(|_| io::Error::from(ErrorKind::InvalidInput))?;
+
+    match (ip, zone) {
+        // an IPv4-mapped address gets connected to over IPv4, which
+        // works even where IPv6 sockets are IPv6 only
+        (ip, None) => Ok((ip.to_canonical(), port).into()),
+        (IpAddr::V6(ip), Some(zone)) => {
+            let scope_id: u32 = zone_index(zone).ok_or_else(|| io::Error::from(ErrorKind::InvalidInput))?;
+            Ok(SocketAddrV6::new(ip, port, 0, scope_id).into())
+        }
+        (IpAddr::V4(_), Some(_)) => Err(ErrorKind::InvalidInput.into()),
+    }
+}
+
+// opens the connection for a scan, binding the local side
+// first if a source port or address was asked for
+pub(crate) async fn connect(address: SocketAddr, options: &ScanOptions) -> io::Result<TcpStream> {
+    let socket: TcpSocket = if address.is_ipv4() { TcpSocket::new_v4()? } else { TcpSocket::new_v6()? };
+
+    // we never send anything, so disabling Nagle just makes sure
+    // nothing gets held back once the connect result is known
+    socket.set_nodelay(true)?;
+
+    // set_linger is deprecated because a nonzero linger can block
+    // on drop while unsent data is flushed, scan sockets never have
+    // any unsent data so it can't block here
+    #[allow(deprecated)]
+    if let Some(linger) = options.linger {
+        socket.set_linger(Some(linger))?;
+    }
+
+    let source_ip: Option<IpAddr> = options.next_source_ip();
+    if options.source_port.is_some() || source_ip.is_some() {
+        let ip: IpAddr = match source_ip {
+            Some(ip) => ip,
+            None if address.is_ipv4() => Ipv4Addr::UNSPECIFIED.into(),
+            None => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        let mut local: SocketAddr = (ip, options.source_port.unwrap_or(0)).into();
+
+        // a link-local address is only on one interface, which has to
+        // be the one the target's zone names for them to reach each other
+        if let (SocketAddr::V6(local), SocketAddr::V6(address)) = (&mut local, address) {
+            if local.ip().is_unicast_link_local() {
+                local.set_scope_id(address.scope_id());
+            }
+        }
+
+        // the previous connection from this port may still be in
+        // TIME_WAIT so we need SO_REUSEADDR to bind it again
+        if options.source_port.is_some() {
+            socket.set_reuseaddr(true)?;
+        }
+        socket.bind(local)?;
+    }
+
+    socket.connect(address).await
+}
+
+// reads the first line the service sends after connecting, giving
+// up if nothing shows up before the timeout. The other side resetting
+// the connection is the only read error that's passed back
+async fn grab_banner<S: AsyncRead + Unpin>(stream: &mut S, timeout: Duration) -> io::Result<Option<String>> {
+    let mut buffer = [0u8; BANNER_SIZE];
+
+    match tokio::time::timeout(timeout, stream.read(&mut buffer)).await {
+        Ok(Ok(size)) if size > 0 => {
+            let banner = String::from_utf8_lossy(&buffer[..size]);
+            let line: String = banner.lines().next().unwrap_or("").trim().chars().filter(|c| !c.is_control()).collect();
+
+            Ok(if line.is_empty() { None } else { Some(line) })
+        }
+        Ok(Err(err)) if err.kind() == ErrorKind::ConnectionReset => Err(err),
+        _ => Ok(None),
+    }
+}
+
+// how long an accepted connection is watched for a reset with
+// ScanOptions::reset_as_open when banners aren't being read, which
+// would have noticed one anyway
+const RESET_WAIT: Duration = Duration::from_millis(100);
+
+// whether the other side reset the connection within timeout of it
+// being accepted, anything it sends or closing normally means it didn't
+async fn was_reset<S: AsyncRead + Unpin>(stream: &mut S, timeout: Duration) -> bool {
+    let mut buffer = [0u8; 1];
+    matches!(tokio::time::timeout(timeout, stream.read(&mut buffer)).await, Ok(Err(err)) if err.kind() == ErrorKind::ConnectionReset)
+}
+
+// closes the connection the polite way, sending a FIN and then
+// reading until the target sends its own, so nothing is left unread
+// when the socket is dropped. A target that never closes its side
+// is given up on after the timeout and dropped as usual
+async fn close_gracefully<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S, timeout: Duration) {
+    if stream.shutdown().await.is_err() {
+        return;
+    }
+
+    let mut buffer = [0u8; BANNER_SIZE];
+    let _ = tokio::time::timeout(timeout, async {
+        while let Ok(size) = stream.read(&mut buffer).await {
+            if size == 0 {
+                break;
+            }
+        }
+    })
+    .await;
+}
+
+// how long a connect waits for a file descriptor to free up when the
+// system has run out, and how many times it waits before giving up.
+// Other connects of the same scan finishing is usually what frees
+// one, so this is about as long as a few timeouts
+const FD_EXHAUSTED_PAUSE: Duration = Duration::from_millis(100);
+const FD_EXHAUSTED_WAITS: u32 = 100;
+
+// whether err is the process or the whole system being out of file
+// descriptors, which says nothing about the port being connected to
+fn is_out_of_files(err: &io::Error) -> bool {
+    #[cfg(unix)]
+    return matches!(err.raw_os_error(), Some(libc::EMFILE | libc::ENFILE));
+
+    #[cfg(not(unix))]
+    return false;
+}
+
+// This is what each thread will run to scan an individual
+// port, giving back whether it was open or why it wasn't. Running out
+// of file descriptors doesn't get recorded against the port, the
+// connect waits for one to free up and tries again, and fd_waits
+// counts the ports that had to. Gives back None if one never freed
+// up, since the port wasn't really scanned
+async fn scan_port(address: SocketAddr, options: &ScanOptions, timeout: Duration, fd_waits: &AtomicUsize) -> Option<PortResult> {
+    let port: u16 = address.port();
+    let mut waits: u32 = 0;
+
+    loop {
+        let start = Instant::now();
+        let connect = tokio::time::timeout(timeout, options.transport().connect(address, options));
+
+        let result: PortResult = match connect.await {
+            Ok(Ok(mut stream)) => {
+                // the latency is only the connect, not the banner after it
+                let latency = start.elapsed();
+
+                // connect succeeds as soon as the handshake does, so a
+                // service that resets the connection right after
+                // accepting it only shows up on the first read
+                let (banner, reset): (Option<String>, bool) = if options.banners {
+                    grab_banner(&mut stream, options.probe_timeout).await.map_or((None, true), |banner| (banner, false))
+                } else if options.reset_as_open {
+                    (None, was_reset(&mut stream, RESET_WAIT.min(options.probe_timeout)).await)
+                } else {
+                    (None, false)
+                };
+                if options.graceful_close && !reset {
+                    close_gracefully(&mut stream, options.probe_timeout).await;
+                }
+
+                PortResult { port, error: None, banner, latency: Some(latency), reset: reset && options.reset_as_open }
+            }
+            Ok(Err(err)) if is_out_of_files(&err) => {
+                if waits == FD_EXHAUSTED_WAITS {
+                    return None;
+                }
+                if waits == 0 {
+                    fd_waits.fetch_add(1, Ordering::SeqCst);
+                }
+                waits += 1;
+                tokio::time::sleep(FD_EXHAUSTED_PAUSE).await;
+                continue;
+            }
+            // a reset that comes right after the handshake can beat the
+            // connect to being noticed, a port that nothing is listening
+            // on refuses the connect instead
+            Ok(Err(err)) if err.kind() == ErrorKind::ConnectionReset && options.reset_as_open => PortResult { port, error: None, banner: None, latency: Some(start.elapsed()), reset: true },
+            Ok(Err(err)) => PortResult { port, error: Some(err.kind()), banner: None, latency: Some(start.elapsed()), reset: false },
+            Err(_) => PortResult { port, error: Some(ErrorKind::TimedOut), banner: None, latency: None, reset: false },
+        };
+
+        return Some(result);
+    }
+}
+
+// scans the port again while it keeps failing with a retryable
+// error, up to the number of retries allowed. Each retry also has to
+// be taken out of retries_left, which every port of the scan shares.
+// None if the port couldn't be scanned for lack of file descriptors
+async fn scan_port_retrying(address: SocketAddr, options: &ScanOptions, retries_left: &AtomicUsize, timeout: &ConnectTimeout, fd_waits: &AtomicUsize) -> Option<PortResult> {
+    let mut result = scan_port(address, options, timeout.get(), fd_waits).await?;
+    timeout.observe(&result);
+
+    for _ in 0..options.retries {
+        match result.error {
+            Some(kind) if options.is_retryable(kind) => {
+                if retries_left.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| left.checked_sub(1)).is_err() {
+                    break;
+                }
+                result = scan_port(address, options, timeout.get(), fd_waits).await?;
+                timeout.observe(&result);
+            }
+            _ => break,
+        }
+    }
+
+    Some(result)
+}
+
+// Scans the given ports on target and returns the report with the
+// result for every port that was scanned. If cancel is triggered no
+// new ports are started, the ones already in flight are awaited, and
+// the partial results are returned
+pub async fn scan(target: &str, ports: Vec<u16>, options: &ScanOptions, cancel: CancellationToken) -> ScanReport {
+    scan_with(target, ports, options, cancel, |_| {}).await
+}
+
+// Same as scan, but on_result is called with each result as soon as
+// it's collected, in the same order that the ports were given. With a
+// first pass the closed ports come first, as the first pass finds them
+pub async fn scan_with<F>(target: &str, ports: Vec<u16>, options: &ScanOptions, cancel: CancellationToken, mut on_result: F) -> ScanReport
+where
+    F: FnMut(&PortResult),
+{
+    let start = Instant::now();
+
+    // the target only gets parsed once, each port's address is this
+    // one with the port swapped in
+    let base: Result<SocketAddr, ErrorKind> = target_address(target, 0).map_err(|err| err.kind());
+    let address = move |port: u16| -> Result<SocketAddr, ErrorKind> {
+        base.map(|mut address| {
+            address.set_port(port);
+            address
+        })
+    };
+
+    // the warmup results are thrown away, they're only there so the
+    // first real ports aren't skewed by cold caches
+    if options.warmup {
+        for address in ports.iter().take(WARMUP_CONNECTS).filter_map(|&port| address(port).ok()) {
+            let _ = scan_port(address, options, options.timeout, &AtomicUsize::new(0)).await;
+        }
+    }
+
+    let addresses: Vec<(u16, Result<SocketAddr, ErrorKind>)> = ports.into_iter().map(|port| (port, address(port))).collect();
+    let scanned: Scanned = match options.first_pass_timeout {
+        Some(first_pass_timeout) => scan_in_two_passes(addresses, options, first_pass_timeout, &cancel, on_result).await,
+        None => scan_addresses(addresses, options, options.abort_after_timeouts, &cancel, |_, result| on_result(result)).await,
+    };
+
+    ScanReport::new(target, scanned, start.elapsed(), cancel.is_cancelled())
+}
+
+// scans addresses quickly with first_pass_timeout and no retries or
+// banners, which is enough to rule out every port that refuses, and
+// then scans the rest again with the whole of options to find out
+// what they really are. A port the second pass never got to is left
+// untested, since the first pass alone can't say if it's open
+async fn scan_in_two_passes<F>(addresses: Vec<(u16, Result<SocketAddr, ErrorKind>)>, options: &ScanOptions, first_pass_timeout: Duration, cancel: &CancellationToken, mut on_result: F) -> Scanned
+where
+    F: FnMut(&PortResult),
+{
+    let quick = ScanOptions {
+        timeout: first_pass_timeout,
+        banners: false,
+        graceful_close: false,
+        retries: 0,
+        timeout_scaling: None,
+        ..options.clone()
+    };
+
+    // a run of timeouts is expected from a timeout this short, so
+    // only the second pass can give up on the target
+    let first: Scanned = scan_addresses(addresses.clone(), &quick, None, cancel, |_, result| {
+        if result.state() == PortState::Closed {
+            on_result(result);
+        }
+    })
+    .await;
+
+    // a port the first pass couldn't get a file descriptor for isn't
+    // known to be closed, so it gets its chance in the second pass
+    let ports: Vec<u16> = addresses.iter().map(|&(port, _)| port).collect();
+    let mut closed: HashMap<u16, PortResult> = first.results.into_iter().filter(|result| result.state() == PortState::Closed).map(|result| (result.port, result)).collect();
+    let candidates: Vec<(u16, Result<SocketAddr, ErrorKind>)> = addresses.into_iter().filter(|(port, _)| !closed.contains_key(port)).collect();
+    let second: Scanned = scan_addresses(candidates, options, options.abort_after_timeouts, cancel, |_, result| on_result(result)).await;
+
+    let mut confirmed: HashMap<u16, PortResult> = second.results.into_iter().map(|result| (result.port, result)).collect();
+    let results: Vec<PortResult> = ports.iter().filter_map(|port| closed.remove(port).or_else(|| confirmed.remove(port))).collect();
+
+    // every port the first pass didn't rule out went to the second, so
+    // only the second's untested ports weren't scanned
+    Scanned {
+        results,
+        peak_concurrency: first.peak_concurrency.max(second.peak_concurrency),
+        retries: second.retries,
+        scaled_timeout: second.scaled_timeout,
+        aborted: second.aborted,
+        untested: second.untested,
+        fd_waits: first.fd_waits + second.fd_waits,
+        fd_exhausted: second.fd_exhausted,
+    }
+}
+
+// Scans a single port on each of targets instead of many ports on one
+// target, for checking which hosts in a list have a port open. The
+// results come back in the same order as targets, and on_result is
+// called with the index of the target along with each one
+pub async fn scan_targets_with<F>(targets: &[String], port: u16, options: &ScanOptions, cancel: CancellationToken, mut on_result: F) -> Vec<PortResult>
+where
+    F: FnMut(usize, &PortResult),
+{
+    let addresses: Vec<(u16, Result<SocketAddr, ErrorKind>)> = targets
+        .iter()
+        .map(|target| (port, target_address(target, port).map_err(|err| err.kind())))
+        .collect();
+
+    // the timeouts are spread over different hosts, so a run of them
+    // doesn't say anything about one host being down
+    scan_addresses(addresses, options, None, &cancel, |index, result| on_result(index, result)).await.results
+}
+
+// how many steps a ramp up grows the concurrency in, evenly spread
+// over the ramp
+const RAMP_UP_STEPS: u32 = 20;
+
+// hands out the permits a ramp up lets scans start with, starting with
+// a twentieth of concurrency and adding the same again every step until
+// there are as many as concurrency. The task doing it has to be aborted
+// if the scan finishes first
+fn ramp_up(concurrency: usize, ramp: Duration) -> (Arc<Semaphore>, tokio::task::JoinHandle<()>) {
+    let step: usize = concurrency.div_ceil(RAMP_UP_STEPS as usize);
+    let permits = Arc::new(Semaphore::new(step));
+
+    let ramping = Arc::clone(&permits);
+    let task = tokio::spawn(async move {
+        let mut interval = tokio::time::interval((ramp / RAMP_UP_STEPS).max(Duration::from_millis(1)));
+        interval.tick().await;
+
+        let mut granted: usize = step;
+        while granted < concurrency {
+            interval.tick().await;
+            let more: usize = step.min(concurrency - granted);
+            ramping.add_permits(more);
+            granted += more;
+        }
+    });
+
+    (permits, task)
+}
+
+// the shortest a scaled timeout can get, so a very fast network
+// doesn't end up with timeouts shorter than scheduling jitter
+const MIN_SCALED_TIMEOUT: Duration = Duration::from_millis(20);
+
+// the connect timeout every port of a scan shares. It's the timeout
+// from the options unless timeout scaling is on, in which case once
+// the first few connects have answered, open or refused, it becomes
+// their median latency times the scaling, never more than the timeout
+// from the options
+struct ConnectTimeout {
+    timeout: Duration,
+    scaling: Option<f64>,
+    samples: Mutex<Vec<Duration>>,
+
+    // the scaled timeout in nanoseconds once it's been picked, 0
+    // until then
+    scaled: AtomicU64,
+}
+
+impl ConnectTimeout {
+    fn new(options: &ScanOptions) -> Self {
+        ConnectTimeout { timeout: options.timeout, scaling: options.timeout_scaling, samples: Mutex::new(Vec::new()), scaled: AtomicU64::new(0) }
+    }
+
+    fn get(&self) -> Duration {
+        self.scaled().unwrap_or(self.timeout)
+    }
+
+    fn scaled(&self) -> Option<Duration> {
+        match self.scaled.load(Ordering::SeqCst) {
+            0 => None,
+            nanos => Some(Duration::from_nanos(nanos)),
+        }
+    }
+
+    // takes the latency of a connect that got an answer, until there
+    // are enough of them to pick the scaled timeout
+    fn observe(&self, result: &PortResult) {
+        let (Some(scaling), Some(latency)) = (self.scaling, result.latency) else {
+            return;
+        };
+        if self.scaled().is_some() {
+            return;
+        }
+
+        let mut samples = self.samples.lock().unwrap();
+        samples.push(latency);
+        if samples.len() < TIMEOUT_SCALING_SAMPLES {
+            return;
+        }
+
+        samples.sort_unstable();
+        let median: Duration = samples[samples.len() / 2];
+        let scaled: Duration = median.mul_f64(scaling).clamp(MIN_SCALED_TIMEOUT, self.timeout.max(MIN_SCALED_TIMEOUT));
+        self.scaled.store(scaled.as_nanos() as u64, Ordering::SeqCst);
+    }
+}
+
+// what scan_addresses found, along with the totals the report
+// needs that only it can count
+struct Scanned {
+    results: Vec<PortResult>,
+    peak_concurrency: usize,
+    retries: usize,
+    scaled_timeout: Option<Duration>,
+    aborted: bool,
+    untested: Vec<u16>,
+    fd_waits: usize,
+    fd_exhausted: Vec<u16>,
+}
+
+// scans every address, each paired with the port it's for so there's
+// still a result to give back if the address couldn't be worked out.
+// After abort_after_timeouts timeouts in a row no more addresses are
+// started, the same as if the scan had been cancelled. on_result is
+// called with the index of each address that got a result
+async fn scan_addresses<F>(addresses: Vec<(u16, Result<SocketAddr, ErrorKind>)>, options: &ScanOptions, abort_after_timeouts: Option<usize>, cancel: &CancellationToken, mut on_result: F) -> Scanned
+where
+    F: FnMut(usize, &PortResult),
+{
+    let mut results = Vec::new();
+    let ports: Vec<u16> = addresses.iter().map(|&(port, _)| port).collect();
+
+    let aborted = AtomicBool::new(false);
+    let mut timeouts_in_a_row: usize = 0;
+
+    // a stream of the scan for each address, buffered so only
+    // concurrency of them are in flight at once and the results
+    // still come out in the order they were given. Addresses that
+    // haven't been started when the scan is cancelled never are, and
+    // with batches the next address waits out the pause after each
+    // batch before it's started
+    let concurrency = options.concurrency();
+    let options = Arc::new(options.clone());
+
+    // counts the scans that are actually running, which can be fewer
+    // than concurrency if the runtime can't keep up
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let peak = Arc::new(AtomicUsize::new(0));
+
+    let retry_budget: usize = options.max_retries_total.unwrap_or(usize::MAX);
+    let retries_left = Arc::new(AtomicUsize::new(retry_budget));
+    let timeout = Arc::new(ConnectTimeout::new(&options));
+    let fd_waits = Arc::new(AtomicUsize::new(0));
+
+    // there's nothing to ramp with only one port at a time
+    let ramp: Option<(Arc<Semaphore>, tokio::task::JoinHandle<()>)> = options.ramp_up.filter(|ramp| !ramp.is_zero() && concurrency > 1).map(|ramp| ramp_up(concurrency, ramp));
+    let permits: Option<Arc<Semaphore>> = ramp.as_ref().map(|(permits, _)| Arc::clone(permits));
+
+    let batch: Option<(usize, Duration)> = options.batch;
+    let scans = stream::iter(addresses.into_iter().enumerate())
+        .then(|(index, address)| async move {
+            if let Some((_, delay)) = batch.filter(|&(size, _)| index > 0 && index % size == 0) {
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = cancel.cancelled() => {}
+                }
+            }
+            (index, address)
+        })
+        .take_while(|_| future::ready(!cancel.is_cancelled() && !aborted.load(Ordering::SeqCst)))
+        .map(|(index, (port, address))| {
+            // have to clone so each thread doesn't own the options
+            let options = Arc::clone(&options);
+            let in_flight = Arc::clone(&in_flight);
+            let peak = Arc::clone(&peak);
+            let retries_left = Arc::clone(&retries_left);
+            let timeout = Arc::clone(&timeout);
+            let fd_waits = Arc::clone(&fd_waits);
+            let permits = permits.clone();
+
+            tokio::spawn(async move {
+                // the semaphore is never closed, so acquiring only waits
+                let _permit = match &permits {
+                    Some(permits) => Some(permits.acquire().await.unwrap()),
+                    None => None,
+                };
+
+                peak.fetch_max(in_flight.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                let result = match address {
+                    Ok(address) => scan_port_retrying(address, &options, &retries_left, &timeout, &fd_waits).await,
+                    Err(kind) => Some(PortResult { port, error: Some(kind), banner: None, latency: None, reset: false }),
+                };
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                result
+            })
+            .map(move |result| (index, port, result))
+        })
+        .buffered(concurrency);
+
+    // the pause between batches is an async block, which has to be
+    // pinned to be polled
+    tokio::pin!(scans);
+
+    // how many addresses were started, whether or not they got a result
+    let mut started: usize = 0;
+    let mut fd_exhausted: Vec<u16> = Vec::new();
+
+    while let Some((index, port, result)) = scans.next().await {
+        started += 1;
+
+        // a task that panicked only loses its own port, it gets
+        // reported as an error instead of taking the rest with it
+        let result: PortResult = match result {
+            Ok(Some(result)) => result,
+            // without a file descriptor there's no answer for the port
+            // at all, so it's untested rather than given a state
+            Ok(None) => {
+                fd_exhausted.push(port);
+                continue;
+            }
+            Err(err) => {
+                eprintln!("Scanning port {} failed: {}", port, err);
+                PortResult { port, error: Some(ErrorKind::Other), banner: None, latency: None, reset: false }
+            }
+        };
+
+        timeouts_in_a_row = if result.error == Some(ErrorKind::TimedOut) { timeouts_in_a_row + 1 } else { 0 };
+        if abort_after_timeouts.is_some_and(|limit| timeouts_in_a_row >= limit) {
+            aborted.store(true, Ordering::SeqCst);
+        }
+
+        on_result(index, &result);
+        results.push(result);
+    }
+
+    if let Some((_, task)) = ramp {
+        task.abort();
+    }
+
+    // the addresses always come back in order, so whatever's after
+    // them was never started
+    let skipped: Vec<u16> = ports[started..].to_vec();
+
+    Scanned {
+        results,
+        peak_concurrency: peak.load(Ordering::SeqCst),
+        retries: retry_budget - retries_left.load(Ordering::SeqCst),
+        scaled_timeout: timeout.scaled(),
+        // hitting the limit on the last few ports doesn't skip anything
+        aborted: aborted.load(Ordering::SeqCst) && !skipped.is_empty(),
+        untested: fd_exhausted.iter().copied().chain(skipped).collect(),
+        fd_waits: fd_waits.load(Ordering::SeqCst),
+        fd_exhausted,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // scans ports on a MockTransport scripted by mock, no network needed
+    async fn scan_mock(mock: MockTransport, ports: Vec<u16>, options: ScanOptionsBuilder) -> ScanReport {
+        let options: ScanOptions = options.transport(Arc::new(mock)).build();
+        scan("127.0.0.1", ports, &options, CancellationToken::new()).await
+    }
+
+    fn states(report: &ScanReport) -> Vec<(u16, PortState)> {
+        report.results.iter().map(|result| (result.port, result.state())).collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn classifies_open_closed_and_filtered() {
+        let mock = MockTransport::new().open(22, Some("SSH-2.0-OpenSSH_9.6\r\n")).port(23, MockPort::Closed).port(24, MockPort::Timeout).port(25, MockPort::Error(ErrorKind::HostUnreachable));
+        let report: ScanReport = scan_mock(mock, vec![22, 23, 24, 25, 26], ScanOptions::builder().banners(true)).await;
+
+        assert_eq!(states(&report), vec![(22, PortState::Open), (23, PortState::Closed), (24, PortState::Filtered), (25, PortState::Filtered), (26, PortState::Closed)]);
+        assert_eq!((report.open, report.closed, report.filtered), (1, 2, 2));
+        assert_eq!(report.results[0].banner.as_deref(), Some("SSH-2.0-OpenSSH_9.6"));
+        assert_eq!(report.results[2].error, Some(ErrorKind::TimedOut));
+        assert_eq!(report.results[2].latency, None);
+        assert_eq!(report.errors[&ErrorKind::ConnectionRefused], 2);
+        assert!(report.untested.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn results_keep_the_order_the_ports_were_given() {
+        let mock = MockTransport::new().open(80, None).open(8080, None);
+        let report: ScanReport = scan_mock(mock, vec![8080, 1, 80], ScanOptions::builder()).await;
+
+        let ports: Vec<u16> = report.results.iter().map(|result| result.port).collect();
+        assert_eq!(ports, vec![8080, 1, 80]);
+        assert_eq!(report.open_ports().count(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_retryable_errors_only() {
+        let mock = MockTransport::new().port(1, MockPort::Timeout).port(2, MockPort::Timeout);
+        let report: ScanReport = scan_mock(mock, vec![1, 2, 3], ScanOptions::builder().retries(2)).await;
+
+        // the closed port isn't worth retrying, the timeouts are
+        assert_eq!(report.retries, 4);
+        assert_eq!(report.filtered, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_stop_at_the_total_budget() {
+        let mock = MockTransport::new().port(1, MockPort::Timeout).port(2, MockPort::Timeout).port(3, MockPort::Timeout);
+        let report: ScanReport = scan_mock(mock, vec![1, 2, 3], ScanOptions::builder().concurrency(1).retries(2).max_retries_total(3)).await;
+
+        assert_eq!(report.retries, 3);
+        assert_eq!(report.filtered, 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn aborts_after_timeouts_in_a_row() {
+        let mut mock = MockTransport::new();
+        for port in 1..=10 {
+            mock = mock.port(port, MockPort::Timeout);
+        }
+        let report: ScanReport = scan_mock(mock, (1..=10).collect(), ScanOptions::builder().concurrency(1).abort_after_timeouts(3)).await;
+
+        assert!(report.aborted);
+        assert_eq!(report.results.len(), 3);
+        assert_eq!(report.untested, (4..=10).collect::<Vec<u16>>());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn an_answer_breaks_a_run_of_timeouts() {
+        let mock = MockTransport::new().port(1, MockPort::Timeout).port(2, MockPort::Timeout).port(4, MockPort::Timeout).port(5, MockPort::Timeout);
+        let report: ScanReport = scan_mock(mock, vec![1, 2, 3, 4, 5], ScanOptions::builder().concurrency(1).abort_after_timeouts(3)).await;
+
+        assert!(!report.aborted);
+        assert_eq!(report.results.len(), 5);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn first_pass_rules_out_closed_ports_and_confirms_the_rest() {
+        let mock = MockTransport::new().open(22, Some("SSH-2.0-OpenSSH_9.6")).port(23, MockPort::Timeout).latency(Duration::from_millis(5));
+        let options: ScanOptions = ScanOptions::builder().first_pass_timeout(Duration::from_millis(50)).banners(true).retries(1).transport(Arc::new(mock)).build();
+
+        let mut seen: Vec<u16> = Vec::new();
+        let report: ScanReport = scan_with("127.0.0.1", vec![21, 22, 23, 24], &options, CancellationToken::new(), |result| seen.push(result.port)).await;
+
+        assert_eq!(states(&report), vec![(21, PortState::Closed), (22, PortState::Open), (23, PortState::Filtered), (24, PortState::Closed)]);
+
+        // the closed ports are known after the first pass, before the
+        // second pass confirms the others with banners and retries
+        assert_eq!(seen, vec![21, 24, 22, 23]);
+        assert_eq!(report.results[1].banner.as_deref(), Some("SSH-2.0-OpenSSH_9.6"));
+        assert_eq!(report.retries, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reset_after_accepting_is_open_reset_only_when_asked() {
+        let mock = MockTransport::new().port(443, MockPort::Reset).open(80, None);
+
+        let report: ScanReport = scan_mock(mock.clone(), vec![80, 443], ScanOptions::builder()).await;
+        assert_eq!(states(&report), vec![(80, PortState::Open), (443, PortState::Open)]);
+
+        let report: ScanReport = scan_mock(mock.clone(), vec![80, 443], ScanOptions::builder().reset_as_open(true)).await;
+        assert_eq!(states(&report), vec![(80, PortState::Open), (443, PortState::OpenReset)]);
+        assert_eq!(report.open, 2);
+
+        let report: ScanReport = scan_mock(mock, vec![80, 443], ScanOptions::builder().reset_as_open(true).banners(true)).await;
+        assert_eq!(states(&report), vec![(80, PortState::Open), (443, PortState::OpenReset)]);
+
+        // the reset can also come back from the connect itself
+        let mock = MockTransport::new().port(443, MockPort::Error(ErrorKind::ConnectionReset));
+        let report: ScanReport = scan_mock(mock.clone(), vec![443], ScanOptions::builder()).await;
+        assert_eq!(states(&report), vec![(443, PortState::Filtered)]);
+
+        let report: ScanReport = scan_mock(mock, vec![443], ScanOptions::builder().reset_as_open(true)).await;
+        assert_eq!(states(&report), vec![(443, PortState::OpenReset)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pauses_between_batches() {
+        let start = tokio::time::Instant::now();
+        let report: ScanReport = scan_mock(MockTransport::new(), vec![1, 2, 3, 4, 5], ScanOptions::builder().batch(2, Duration::from_secs(1))).await;
+
+        // two pauses, after the first and second batches
+        assert_eq!(report.results.len(), 5);
+        assert!(start.elapsed() >= Duration::from_secs(2));
+        assert!(start.elapsed() < Duration::from_secs(3));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn running_out_of_files_leaves_the_port_untested() {
+        let mock = MockTransport::new().open(1, None).port(2, MockPort::OutOfFiles);
+        let report: ScanReport = scan_mock(mock, vec![1, 2, 3], ScanOptions::builder()).await;
+
+        assert_eq!(states(&report), vec![(1, PortState::Open), (3, PortState::Closed)]);
+        assert_eq!(report.fd_waits, 1);
+        assert_eq!(report.fd_exhausted, vec![2]);
+        assert_eq!(report.untested, vec![2]);
+        assert!(!report.aborted);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancelling_leaves_the_rest_untested() {
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        let options: ScanOptions = ScanOptions::builder().transport(Arc::new(MockTransport::new())).build();
+        let report: ScanReport = scan("127.0.0.1", vec![1, 2, 3], &options, cancel).await;
+
+        assert!(report.cancelled);
+        assert!(report.results.is_empty());
+        assert_eq!(report.untested, vec![1, 2, 3]);
+    }
+}
@@ -1,192 +1,2394 @@
-// clap for CLI args and dns_lookup for host lookup
-use clap::Parser;
-use dns_lookup::lookup_host;
+// the scanning engine lives in the library half of the crate
+use badmap::{cdn_provider, exposure_score, get_service_by_port, scan, scan_targets_with, scan_with, services, sweep, CancellationToken, Cidr, Fingerprint, FingerprintDb, PortResult, ScanOptions, ScanReport, ScopePolicy, SshJump, SweepMethod, SweepResult, TIMEOUT_SCALING_SAMPLES};
 
-// tokio to asynchronously scan ports, write to files, etc.
-use tokio::task;
-use tokio::net::TcpStream;
+// settings that come from the config file
+mod config;
+use config::{load_config, save_profile, Config};
+
+// working out which ports to scan
+mod ports;
+use ports::{format_port_spec, parse_port_spec, prioritize_ports, top_ports};
+
+// working out which hosts to scan
+mod targets;
+use targets::{is_ip_range, normalize_ip, parse_ip_range, parse_ip_target, parse_targets, strip_zone, read_targets_file, resolve_hostnames, resolve_srv, Resolver, Target};
+
+// comparing saved reports, for badmap diff
+mod diff;
+use diff::{load_baseline, run_diff, Baseline};
+
+// declarative scan definitions for --job
+mod job;
+use job::{load_job, Job};
+
+// reusing the results of a recent identical scan
+mod cache;
+use cache::{cache_key, load_cached};
+
+// rendering the report in each of the output formats
+mod output;
+use output::{format_closed_since_baseline, format_firewall_probe, format_grouped_ports, format_http_checks, format_open_host, format_port_line, format_state_counts, format_summary, get_service, render, render_summary_only, render_sweep, render_vertical, sort_open_ports, FirewallProbe, Format, OpenHost, OutputSort, PortReport, PortUptime, RepeatSummary, Report, StateCounts, Template};
+
+// checksums and signatures for saved reports
+mod sign;
+use sign::{load_signing_key, sha256_hex, sign_output_file};
+
+// checking a path on open web ports, for --http-check
+mod http_check;
+use http_check::{check_http, HttpCheck};
+
+// picking which open ports to report, for --filter
+mod filter;
+use filter::Filter;
+
+// logging what changed from one scan to the next, for --changelog
+mod changelog;
+use changelog::append_changes;
+
+// handing the open services over to other tools, for
+// --export-inventory
+mod inventory;
+use inventory::{write_inventory, InventoryFormat};
+
+// keeping a history of scans in SQLite
+mod sqlite;
+use sqlite::write_sqlite;
+
+// clap for CLI args, chrono for report timestamps, regex for
+// filtering banners, and rand for sampling ports
+use chrono::{SecondsFormat, Utc};
+use clap::{Parser, Subcommand, ValueEnum};
+use regex::Regex;
+use rand::rngs::StdRng;
+use rand::seq::{IndexedRandom, SliceRandom};
+use rand::SeedableRng;
+
+// tokio to asynchronously write to files, etc., futures to scan
+// several targets at once, and async_compression to gzip output files
+use async_compression::tokio::write::GzipEncoder;
+use futures::{future, stream, StreamExt};
 use tokio::fs::File;
-use tokio::io::AsyncWriteExt;
-use tokio::sync::Semaphore;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 
 // other imports
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
 use std::process::exit;
-use std::net::IpAddr;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+// the scope policy is read from a fixed path rather than a flag so
+// whoever runs the scan can't just leave it off, locked-down builds
+// can point it somewhere else with BADMAP_SCOPE_FILE at compile time
+const SCOPE_FILE: &str = match option_env!("BADMAP_SCOPE_FILE") {
+    Some(path) => path,
+    None => "/etc/badmap/scope.conf",
+};
+
+// prints a line of the interactive scan output, unless the report
+// itself is going to stdout in which case stdout has to stay
+// clean for whatever it's piped into
+macro_rules! status {
+    ($args:expr) => {
+        if !$args.report_to_stdout() {
+            println!();
+        }
+    };
+    ($args:expr, $($arg:tt)*) => {
+        if !$args.report_to_stdout() {
+            println!($($arg)*);
+        }
+    };
+}
+
+// reports an error that stops the run and exits. With --format json
+// the error is a json object with a kind so whatever reads the report
+// can still parse it, going to stdout if that's where the report was
+// going to go
+macro_rules! fail {
+    ($args:expr, $kind:expr, $($arg:tt)*) => {{
+        let message: String = format!($($arg)*);
+        if $args.format() == Format::Json {
+            let error: String = serde_json::json!({ "error": message, "kind": $kind }).to_string();
+            if $args.report_to_stdout() {
+                println!("{}", error);
+            } else {
+                eprintln!("{}", error);
+            }
+        } else {
+            eprintln!("{}", message);
+        }
+        exit(1)
+    }};
+}
+
+// prints one line of --explain narration, on stderr so it's there
+// even when the report itself is going to stdout
+macro_rules! explain {
+    ($args:expr, $($arg:tt)*) => {
+        if $args.explain {
+            eprintln!("Explain: {}", format!($($arg)*));
+        }
+    };
+}
+
+// how long --retry-scan waits before scanning again, long enough
+// for a flaky link or VPN to come back
+const RETRY_SCAN_DELAY: Duration = Duration::from_secs(10);
 
 // define CLI args using clap
+// the whole command line, a subcommand or the scan flags on their own
+// the way badmap was run before there were subcommands
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, args_override_self = true, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    args: Args,
+}
+
+// everything a scan can be given, under badmap scan
+#[derive(clap::Args, Debug)]
+#[group(skip)]
 struct Args {
+    /// IPv4 address to scan, a range of them like 192.168.1.10-192.168.1.50, or an IPv6 link-local address with its zone like fe80::1%eth0
     #[arg(short, long, default_value = "")]
     ip: String,
 
+    /// Domain name to scan, or with --ip just the name to put on the report
     #[arg(short, long, default_value = "")]
     domain: String,
 
-    #[arg(short, long, default_value = "output.txt")]
+    /// File of IP addresses and hostnames to scan, one per line
+    #[arg(short, long)]
+    targets_file: Option<String>,
+
+    /// Look up the SRV records of this name, like _sip._tcp.example.com, and scan each host they point at on only the port its record gives
+    #[arg(long, conflicts_with_all = ["ports", "all_ports", "sweep", "vertical", "monitor"])]
+    srv: Option<String>,
+
+    /// Look up every hostname in the targets file each time it shows up (for round-robin DNS)
+    #[arg(long)]
+    no_dns_cache: bool,
+
+    /// Only look up the hostnames in the targets and print what each resolved to, then exit without scanning
+    #[arg(long, conflicts_with_all = ["ip", "srv", "sweep", "monitor"])]
+    resolve_only: bool,
+
+    /// Only check which hosts in this range are up (ICMP, or TCP connects when ICMP isn't allowed)
+    #[arg(long, conflicts_with_all = ["ip", "domain", "targets_file", "template", "ssh_jump"])]
+    sweep: Option<String>,
+
+    /// Where to save the report, - writes it to stdout and names ending in .gz are gzip compressed, and a FIFO or unix socket that already exists is written to without truncating (by default a timestamped file under ~/.local/state/badmap)
+    #[arg(short, long, default_value = "")]
     output_filename: String,
+
+    /// If the output file already exists, save to the first free name like output-1.txt instead of overwriting it
+    #[arg(long)]
+    no_clobber: bool,
+
+    /// Also record the scan in this SQLite database, adding to whatever scans are already in it
+    #[arg(long)]
+    sqlite: Option<String>,
+
+    /// Append every port that opened or closed since the last run to this file, starting from the --monitor baseline for targets it hasn't seen yet
+    #[arg(long)]
+    changelog: Option<String>,
+
+    /// Also write the open ports of recognized services to this file for other tools, in --inventory-format
+    #[arg(long)]
+    export_inventory: Option<String>,
+
+    /// What --export-inventory writes, address and service lines like /etc/hosts or an Ansible inventory grouped by service
+    #[arg(long, value_enum, default_value_t = InventoryFormat::Hosts)]
+    inventory_format: InventoryFormat,
+
+    /// Make sure the saved report is on disk before exiting, so it survives a crash or power loss
+    #[arg(long, alias = "output-fsync")]
+    fsync: bool,
+
+    /// Write a SHA-256 of the saved report to a .sha256 file next to it
+    #[arg(long)]
+    sign: bool,
+
+    /// Also sign the saved report with this ed25519 PKCS#8 PEM key, into a .sig file next to it
+    #[arg(long, requires = "sign")]
+    sign_key: Option<String>,
+
+    /// Format of the saved report, by default picked from the output filename (.json, .csv, otherwise text), json also prints fatal errors as json
+    #[arg(short, long, value_enum)]
+    format: Option<Format>,
+
+    /// Write each open port in the report as this template instead of a built-in format,
+    /// using {port}, {state}, {service}, {latency}, and {banner}
+    #[arg(long, conflicts_with = "format")]
+    template: Option<String>,
+
+    /// Save only the target, open port count, open ports, and scan duration as compact json
+    #[arg(long, conflicts_with_all = ["format", "template"])]
+    summary_only: bool,
+
+    /// Line to start a templated report with, using {target} and {open_count}
+    #[arg(long, requires = "template")]
+    template_header: Option<String>,
+
+    /// Line to end a templated report with, using {target} and {open_count}
+    #[arg(long, requires = "template")]
+    template_footer: Option<String>,
+
+    /// Describe the scan plan before starting and explain decisions made during the scan
+    #[arg(long)]
+    explain: bool,
+
+    /// List open ports grouped by service category (web, database, remote-access, mail, other) once each target is done
+    #[arg(long)]
+    group_by_category: bool,
+
+    /// The order the open ports are listed in the report, by port, service name, latency (slowest first), or discovery (the order they were scanned in)
+    #[arg(long, value_enum, default_value_t = OutputSort::Discovery)]
+    output_sort: OutputSort,
+
+    /// Only print this many open ports of each scan, the output file still gets all of them
+    #[arg(long)]
+    head: Option<usize>,
+
+    /// Don't print the "Starting BadMap" line or the report header for each target
+    #[arg(long)]
+    no_banner: bool,
+
+    /// Print each port that wasn't open as closed or filtered, with the reason why, and the peak memory used
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Every this many seconds, print how far the scan has got to stderr so a long quiet scan doesn't look hung
+    #[arg(long)]
+    heartbeat: Option<u64>,
+
+    /// Summarize errors by kind at the end of the scan instead of per port
+    #[arg(long)]
+    quiet_errors: bool,
+
+    /// Exit with an error listing any open port that isn't in this port spec, like 443 or 22,443 (for CI policy checks)
+    #[arg(long)]
+    allowed_ports: Option<String>,
+
+    /// Report how many ports were open, closed, and filtered without listing the ones that weren't open
+    #[arg(long)]
+    report_closed_count: bool,
+
+    /// After scanning, connect to this many random high ports that are almost certainly closed and report whether the host's firewall rejects (closed) or drops (filtered) them
+    #[arg(long, value_name = "COUNT", conflicts_with_all = ["sweep", "vertical"])]
+    firewall_probe: Option<usize>,
+
+    /// Ports to scan, like 22,80,8000-8100, or - for every port (the default is the 1000 most common ports)
+    #[arg(short, long, conflicts_with = "all_ports")]
+    ports: Option<String>,
+
+    /// Scan every port from 1 to 65535 instead of the 1000 most common ones
+    #[arg(long)]
+    all_ports: bool,
+
+    /// Only scan the ports in a named profile (web, db, mail, remote), or use the flags saved as a profile with --save-profile
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// Save the flags given here, other than the targets and output file, as a profile in the config file for --profile to load later
+    #[arg(long)]
+    save_profile: Option<String>,
+
+    /// Do a few throwaway connects to the target before scanning to warm up caches
+    #[arg(long)]
+    warmup: bool,
+
+    /// Bind every connection to this local port (forces scanning one port at a time)
+    #[arg(long)]
+    source_port: Option<u16>,
+
+    /// Scan from this network interface's address of the same family as the targets
+    #[arg(long)]
+    interface: Option<String>,
+
+    /// Bind connections to this local address, given more than once the connections take turns between them, and ADDRESS=WEIGHT gives one that many turns
+    #[arg(long, value_name = "ADDRESS[=WEIGHT]", conflicts_with = "interface")]
+    source_ip: Vec<String>,
+
+    /// Set SO_LINGER on scan sockets to this many seconds (0 resets connections on close)
+    #[arg(long)]
+    so_linger: Option<u64>,
+
+    /// After connecting, send a FIN and wait for the target to close its side instead of just dropping the connection
+    #[arg(long, conflicts_with = "so_linger")]
+    graceful_close: bool,
+
+    /// Watch each accepted connection for a reset right after the handshake and report those ports as open|reset instead of open (a firewall that resets connections looks the same)
+    #[arg(long)]
+    reset_as_open: bool,
+
+    /// Scan through this SSH jump host (user@host or user@host:port, or user@[v6 address]:port), its key has to be in known_hosts
+    #[arg(long)]
+    ssh_jump: Option<String>,
+
+    /// Private key to log in to the jump host with instead of ~/.ssh/id_ed25519 or ~/.ssh/id_rsa
+    #[arg(long, requires = "ssh_jump")]
+    ssh_key: Option<String>,
+
+    /// Run the jump host's SSH session over this already connected TCP socket instead of connecting to it (the socket has to be inherited as this file descriptor)
+    #[cfg(unix)]
+    #[arg(long, requires = "ssh_jump")]
+    tunnel_fd: Option<i32>,
+
+    /// Grab the banner of each open port and use it to identify the service
+    #[arg(short, long)]
+    banners: bool,
+
+    /// Fingerprint file of nmap style match lines to identify products and versions from banners, tried before the built-in ones
+    #[arg(long, requires = "banners")]
+    fingerprints: Option<String>,
+
+    /// GET this path, like /healthz, from every open HTTP or HTTPS port and report the status code and where any redirect points
+    #[arg(long)]
+    http_check: Option<String>,
+
+    /// Only report open ports whose banner matches this regex
+    #[arg(long, requires = "banners")]
+    banner_regex: Option<String>,
+
+    /// Only report open ports that match this expression, like 'port>1024 && service!=unknown' (fields are port, latency, state, service, and banner)
+    #[arg(long)]
+    filter: Option<String>,
+
+    /// Only report open ports that aren't in the built-in port to service table
+    #[arg(long)]
+    only_unexpected: bool,
+
+    /// Seconds to wait for a banner after connecting
+    #[arg(long, default_value_t = 2)]
+    probe_timeout: u64,
+
+    /// Refuse to scan more than this many host and port pairs unless --force is given
+    #[arg(long, default_value_t = 10_000_000)]
+    ports_expand_limit: u64,
+
+    /// Scan even if there are more host and port pairs than --ports-expand-limit
+    #[arg(long)]
+    force: bool,
+
+    /// Only scan the ports that were open in this saved json report (a file or http(s):// URL) and report any that have closed, on the report's own targets unless others are given
+    #[arg(long)]
+    monitor: Option<String>,
+
+    /// Scan the ports from highest to lowest
+    #[arg(long)]
+    reverse: bool,
+
+    /// Only scan a random sample of this many ports out of the ones selected
+    #[arg(long)]
+    sample_ports: Option<usize>,
+
+    /// Scan these of the selected ports first, in this order, like 443,22 (the rest follow in the usual order)
+    #[arg(long)]
+    priority_ports: Option<String>,
+
+    /// Scan the targets in a random order instead of the order they were given
+    #[arg(long)]
+    randomize_targets: bool,
+
+    /// Seed for anything random so a scan can be reproduced
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Start the output file with an ISO-8601 timestamp of when the report was written
+    #[arg(long)]
+    output_append_timestamp: bool,
+
+    /// Put the command line the scan was run with at the top of the report, along with the BadMap version
+    #[arg(long)]
+    output_include_command: bool,
+
+    /// Scan each port across every target before moving on to the next port, and report by port instead of by host
+    #[arg(long, conflicts_with_all = ["template", "summary_only", "group_by_category", "sqlite", "cache_ttl", "repeat", "report_closed_count", "http_check", "allowed_ports", "monitor", "changelog", "export_inventory", "output_sort"])]
+    vertical: bool,
+
+    /// Scan the same ports this many times and report which ones changed state
+    #[arg(long, default_value_t = 1)]
+    repeat: u32,
+
+    /// Seconds to wait between repeated scans
+    #[arg(long, default_value_t = 60)]
+    interval: u64,
+
+    /// How many ports of a target to scan at the same time [default: 1000]
+    #[arg(long)]
+    concurrency: Option<usize>,
+
+    /// Start each target at a twentieth of --concurrency and grow to all of it over this many seconds, so the first ports aren't lost to a burst of connects
+    #[arg(long)]
+    ramp_up: Option<u64>,
+
+    /// Start connects in bursts of this many, pausing for --batch-delay after each burst instead of keeping a steady stream going
+    #[arg(long)]
+    batch_size: Option<usize>,
+
+    /// Milliseconds to pause after each --batch-size burst of connects
+    #[arg(long, default_value_t = 1000, requires = "batch_size")]
+    batch_delay: u64,
+
+    /// How many targets to scan at the same time, each with its own --concurrency, so up to both multiplied together connects are in flight (the live output of targets scanned together is interleaved, the saved report isn't)
+    #[arg(long, conflicts_with_all = ["source_port", "vertical"])]
+    max_hosts_concurrent: Option<usize>,
+
+    /// Seconds to wait before starting each target after the first, once there's room for it under --max-hosts-concurrent
+    #[arg(long, default_value_t = 0)]
+    inter_host_delay: u64,
+
+    /// Give up on a target after this many ports in a row time out, listing the ports that weren't scanned
+    #[arg(long)]
+    abort_after_timeouts: Option<usize>,
+
+    /// Once a few connects have answered, wait this many times their median latency on each connect instead of the full timeout
+    #[arg(long)]
+    timeout_scaling: Option<f64>,
+
+    /// Scan every port once with this short connect timeout in milliseconds, then scan only the ones that didn't refuse again with the normal timeout and retries to confirm them
+    #[arg(long)]
+    first_pass_timeout: Option<u64>,
+
+    /// Try a port up to this many more times when it fails with a retryable error (timeouts and resets by default)
+    #[arg(long, default_value_t = 0)]
+    retries: u32,
+
+    /// Stop retrying for the rest of a target's scan once this many retries have been made across all its ports
+    #[arg(long)]
+    max_retries_total: Option<usize>,
+
+    /// Reuse the results of the same scan if it was run within this many seconds
+    #[arg(long)]
+    cache_ttl: Option<u64>,
+
+    /// Scan again once if nothing was open and most ports were unreachable
+    #[arg(long)]
+    retry_scan: bool,
+
+    /// Config file to use instead of ~/.config/badmap/config.toml
+    #[arg(short, long)]
+    config: Option<String>,
+
+    /// Print the built-in port to service table and exit
+    #[arg(long)]
+    list_services: bool,
+
+    /// Print a JSON description of this build's version, formats, protocols, and features, then exit
+    #[arg(long)]
+    capabilities: bool,
+
+    /// Run the scan described in this YAML or JSON job file, flags given here override it
+    #[arg(long)]
+    job: Option<String>,
+
+    // the targets and ports from the job file, which have no flags
+    #[arg(skip)]
+    job_targets: Vec<String>,
+
+    #[arg(skip)]
+    job_ports: Option<Vec<u16>>,
+
+    // --filter once it's been parsed
+    #[arg(skip)]
+    parsed_filter: Option<Filter>,
+
+    // the command line after expanding any job file and saved profile,
+    // which is what --save-profile saves
+    #[arg(skip)]
+    command_line: Vec<String>,
+
+    // the saved profile whose flags were used, if --profile named one
+    #[arg(skip)]
+    saved_profile: Option<String>,
+}
+
+// the things badmap can do. Scanning is also what it does with no
+// subcommand, which still works but is on its way out
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Scan ports on one or more targets
+    #[command(args_override_self = true)]
+    Scan(Box<Args>),
+
+    /// Compare two saved json reports and print the ports that opened, closed, or changed
+    Diff {
+        /// The older report, a file or an http(s):// URL
+        old: String,
+
+        /// The newer report, a file or an http(s):// URL
+        new: String,
+    },
+
+    /// Print the built-in port to service table
+    ListServices,
+}
+
+impl Args {
+    // an output filename of - means the report goes to stdout
+    fn report_to_stdout(&self) -> bool {
+        self.output_filename == "-"
+    }
+
+    // whether the output filename is something that already exists and
+    // isn't a regular file, like a FIFO or a socket another program is
+    // reading from. Those are written to as they are, never truncated
+    // or renamed, and there's nothing to sync or put a sidecar next to
+    fn report_to_stream(&self) -> bool {
+        std::fs::metadata(&self.output_filename).is_ok_and(|metadata| !metadata.is_file() && !metadata.is_dir())
+    }
+
+    // whether there's a list of targets rather than just one, even
+    // if the list turns out to only have one target in it
+    fn is_target_list(&self) -> bool {
+        self.targets_file.is_some() || self.srv.is_some() || !self.job_targets.is_empty() || is_ip_range(&self.ip) || (self.monitor.is_some() && !self.has_targets())
+    }
+
+    // whether any targets were given, --monitor falls back on the
+    // baseline's targets without them
+    fn has_targets(&self) -> bool {
+        !self.ip.is_empty() || !self.domain.is_empty() || self.targets_file.is_some() || self.srv.is_some() || !self.job_targets.is_empty()
+    }
+
+    // whether the ports were picked on purpose, rather than being the
+    // default of the 1000 most common
+    fn has_ports(&self) -> bool {
+        self.ports.is_some() || self.profile.is_some() || self.all_ports || self.job_ports.is_some()
+    }
+
+    // the format that was asked for, or the one the output filename's
+    // extension implies, looking past a .gz on the end
+    fn format(&self) -> Format {
+        if let Some(format) = self.format {
+            return format;
+        }
+
+        let filename: &str = self.output_filename.strip_suffix(".gz").unwrap_or(&self.output_filename);
+        match filename.rsplit_once('.').map(|(_, extension)| extension.to_ascii_lowercase()).as_deref() {
+            Some("json") => Format::Json,
+            Some("csv") => Format::Csv,
+            _ => Format::Text,
+        }
+    }
 }
 
-// holds mappings from well_known_ports to it's corresponding
-// service
-fn get_service_by_port(port: u16) -> Option<&'static str> {
-    match port {
-        22 => Some("SSH"),
-        80 => Some("HTTP"),
-        443 => Some("HTTPS"),
+// holds the built-in named port profiles for common
+// task-focused scans
+fn get_ports_by_profile(profile: &str) -> Option<Vec<u16>> {
+    match profile {
+        "web" => Some(vec![80, 443, 8080, 8443]),
+        "db" => Some(vec![3306, 5432, 6379, 27017, 1433]),
+        "mail" => Some(vec![25, 110, 143, 465, 587, 993, 995]),
+        "remote" => Some(vec![22, 23, 3389, 5900]),
         _ => None,
     }
 }
 
-// gets and validates IP address for host that we want to scan
-// ports on
-async fn get_target(args: &Args) -> String {
-    let target: String = if !args.ip.is_empty() && args.domain.is_empty() {
-        match args.ip.parse::<IpAddr>() {
-            Ok(ip) if ip.is_ipv4() => ip.to_string(),
-            Ok(_) => {
-                eprintln!("The provided IP address is not an IPv4 address: {}", args.ip);
-                exit(1);
+// gets the list of ports that we want to scan, which is the 1000
+// most common ports unless a job listed them, they were given with
+// --ports or --all-ports, or a profile was given
+fn get_ports(args: &Args) -> Vec<u16> {
+    // this isn't left to clap since a saved profile can be overridden
+    // with --ports, it's only a built-in one that can't
+    if args.profile.is_some() && (args.ports.is_some() || args.all_ports) {
+        fail!(args, "usage", "--profile {} can't be used with --ports or --all-ports, it's a list of ports itself", args.profile.as_deref().unwrap_or(""));
+    }
+
+    if let Some(ports) = &args.job_ports {
+        return ports.clone();
+    }
+
+    if let Some(spec) = &args.ports {
+        return match parse_port_spec(spec) {
+            Ok(ports) => ports,
+            Err(err) => fail!(args, "usage", "Invalid port spec {}: {}", spec, err),
+        };
+    }
+
+    match &args.profile {
+        Some(profile) => match get_ports_by_profile(profile) {
+            Some(ports) => ports,
+            None => {
+                fail!(args, "usage", "Unknown port profile: {} (expected web, db, mail, remote, or a profile saved with --save-profile)", profile);
             }
-            Err(_) => {
-                eprintln!("Invalid IP address provided: {}", args.ip);
-                exit(1);
+        },
+        None if args.all_ports => (1..=65535).collect(),
+        None => top_ports(),
+    }
+}
+
+// prints the built-in port to service table in the same
+// columns as the scan output
+fn list_services() {
+    println!("PORT  SERVICE\n");
+
+    for (port, service) in services() {
+        println!("{}  {}", port, service);
+    }
+}
+
+// prints what this build can do as JSON, for wrapper scripts that
+// need to check for something before relying on it. Only TCP connect
+// scans exist, UDP and SYN scans are listed as unsupported so the
+// answer is a false rather than a missing key
+fn print_capabilities() {
+    let formats: Vec<String> = Format::value_variants().iter().filter_map(|format| format.to_possible_value()).map(|value| value.get_name().to_string()).collect();
+
+    let mut features: Vec<&str> = vec!["banners", "fingerprints", "http-check", "ssh-jump", "sweep", "cache", "gzip", "sqlite", "sign", "diff", "monitor", "job", "srv"];
+    if cfg!(unix) {
+        features.push("tunnel-fd");
+    }
+
+    let capabilities = serde_json::json!({
+        "version": option_env!("CARGO_PKG_VERSION").unwrap_or("<uknown>"),
+        "formats": formats,
+        "protocols": { "tcp": true, "udp": false, "syn": false },
+        "sweep_methods": ["icmp", "tcp"],
+        "features": features,
+    });
+    println!("{}", serde_json::to_string_pretty(&capabilities).unwrap());
+}
+
+// randomly picks count of the ports, the seed makes it so the
+// same sample can be picked again
+fn sample_ports(ports: Vec<u16>, count: usize, seed: u64) -> Vec<u16> {
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let mut sample: Vec<u16> = ports.sample(&mut rng, count).copied().collect();
+    sample.sort_unstable();
+    sample
+}
+
+// puts the targets in a random order, the seed makes it so the
+// same order can be picked again
+fn shuffle_targets(targets: &mut [Target], seed: u64) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    targets.shuffle(&mut rng);
+}
+
+// gets and validates the hosts that we want to scan ports on,
+// either the single IP address or domain or everything in the
+// targets file
+async fn get_targets(args: &Args) -> Vec<Target> {
+    let mut resolver = Resolver::new(!args.no_dns_cache);
+
+    // an IP address and domain can be given together, in which case
+    // the domain is only the label and the IP address is scanned
+    let given: usize = [!args.ip.is_empty() || !args.domain.is_empty(), args.targets_file.is_some(), args.srv.is_some(), !args.job_targets.is_empty()]
+        .iter()
+        .filter(|&&given| given)
+        .count();
+    if given != 1 {
+        fail!(args, "usage", "Either an IP address (-i or --ip), Domain Name (-d or --domain), targets file (-t or --targets-file), SRV name (--srv), or job file with targets (--job) needs to be provided");
+    }
+
+    let targets: Result<Vec<Target>, String> = if let Some(targets_file) = &args.targets_file {
+        read_targets_file(targets_file, &mut resolver).await
+    } else if let Some(srv) = &args.srv {
+        resolve_srv(srv, &mut resolver).await.inspect(|targets| {
+            for target in targets {
+                explain!(args, "{} points at {} port {}, which resolved to {}", srv, target.domain.as_deref().unwrap_or(""), target.port.unwrap_or(0), target.ip);
             }
+        })
+    } else if !args.job_targets.is_empty() {
+        Ok(parse_targets(args.job_targets.iter().map(String::as_str), &mut resolver).await)
+    } else if is_ip_range(&args.ip) && !args.domain.is_empty() {
+        Err(format!("A domain can't be used to label a range of IP addresses: {}", args.ip))
+    } else if is_ip_range(&args.ip) {
+        parse_ip_range(&args.ip, if args.force { u64::MAX } else { args.ports_expand_limit })
+    } else if !args.ip.is_empty() && !args.domain.is_empty() {
+        parse_ip_target(&args.ip, Some(args.domain.clone())).map(|target| vec![target])
+    } else if !args.ip.is_empty() {
+        parse_ip_target(&args.ip, None).map(|target| vec![target])
+    } else if args.domain.parse::<IpAddr>().is_ok() {
+        // an address given as the domain doesn't need DNS at all
+        explain!(args, "{} was given as a domain but is an IP address, so it's scanned without a lookup", args.domain);
+        parse_ip_target(&args.domain, None).map(|target| vec![target])
+    } else {
+        resolver.resolve_target(&args.domain).await.map(|target| vec![target])
+    };
+
+    match targets {
+        Ok(targets) if targets.is_empty() => {
+            fail!(args, "target", "No usable targets were found");
         }
-    } else if !args.domain.is_empty() && args.ip.is_empty() {
-        let domain = args.domain.clone();
-        match task::spawn_blocking(move || lookup_host(&domain)).await.unwrap() {
-            Ok(ips) => {
-                match ips.into_iter().find(|ip| ip.is_ipv4()) {
-                    Some(ipv4) => ipv4.to_string(),
-                    None => {
-                        eprintln!("Failed to resolve domain into IPv4 address: {}", args.domain);
-                        exit(1)
-                    }
-                }
+        Ok(targets) => targets,
+        Err(err) => {
+            fail!(args, "target", "{}", err);
+        }
+    }
+}
+
+// looks up every hostname in the targets and prints each one with the
+// addresses it resolved to, or why it didn't resolve, for
+// --resolve-only. Exits with an error when any of them didn't, so a
+// target list can be checked in a script before it gets scanned
+async fn print_resolutions(args: &Args) {
+    let mut resolver = Resolver::new(!args.no_dns_cache);
+
+    let contents: String = match &args.targets_file {
+        Some(targets_file) => match tokio::fs::read_to_string(targets_file).await {
+            Ok(contents) => contents,
+            Err(err) => fail!(args, "target", "Failed to read targets file {}: {}", targets_file, err),
+        },
+        None if !args.job_targets.is_empty() => args.job_targets.join("\n"),
+        None => args.domain.clone(),
+    };
+
+    let resolutions: Vec<(&str, Result<Vec<IpAddr>, String>)> = resolve_hostnames(contents.lines(), &mut resolver).await;
+    if resolutions.is_empty() {
+        fail!(args, "target", "No hostnames to resolve, --resolve-only needs a domain (-d or --domain), targets file (-t or --targets-file), or job file with targets (--job)");
+    }
+
+    let failed: usize = resolutions.iter().filter(|(_, answer)| answer.is_err()).count();
+    if args.format() == Format::Json {
+        let resolutions: Vec<serde_json::Value> = resolutions
+            .iter()
+            .map(|(hostname, answer)| match answer {
+                Ok(ips) => serde_json::json!({ "hostname": hostname, "addresses": ips }),
+                Err(err) => serde_json::json!({ "hostname": hostname, "error": err }),
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&resolutions).unwrap());
+    } else {
+        let width: usize = resolutions.iter().map(|(hostname, _)| hostname.len()).max().unwrap_or(0);
+        for (hostname, answer) in &resolutions {
+            match answer {
+                Ok(ips) => println!("{:width$}  {}", hostname, ips.iter().map(IpAddr::to_string).collect::<Vec<String>>().join(", ")),
+                Err(err) => println!("{:width$}  FAILED ({})", hostname, err),
             }
-            Err(_) => {
-                eprintln!("Failed to resolve domain: {}", args.domain);
-                exit(1)
+        }
+    }
+
+    if failed > 0 {
+        fail!(args, "target", "{} of {} hostnames didn't resolve", failed, resolutions.len());
+    }
+}
+
+// the targets of the baseline report, for --monitor without any
+// targets of its own
+fn get_baseline_targets(args: &Args, baseline: &[Baseline]) -> Vec<Target> {
+    let targets: Vec<Target> = baseline
+        .iter()
+        .filter_map(|baseline| match parse_ip_target(&baseline.target, baseline.domain.clone()) {
+            Ok(target) => Some(target),
+            Err(err) => {
+                eprintln!("Skipping target: {}", err);
+                None
             }
+        })
+        .collect();
+
+    if targets.is_empty() {
+        fail!(args, "target", "No usable targets were found in {}", args.monitor.as_deref().unwrap_or(""));
+    }
+
+    targets
+}
+
+// refuses to go any further if there's a scope policy and any of the
+// targets aren't allowed by it, a policy that can't be read or parsed
+// also stops the scan since we can't tell what it would have allowed.
+// Fallback addresses the policy doesn't allow are just dropped. Gives
+// back whether there was a policy to check against
+async fn enforce_scope_policy(args: &Args, targets: &mut [Target]) -> bool {
+    let contents: String = match tokio::fs::read_to_string(SCOPE_FILE).await {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == ErrorKind::NotFound => return false,
+        Err(err) => {
+            fail!(args, "scope", "Failed to read scope policy {}: {}", SCOPE_FILE, err);
+        }
+    };
+
+    let policy: ScopePolicy = match ScopePolicy::parse(&contents) {
+        Ok(policy) => policy,
+        Err(err) => {
+            fail!(args, "scope", "Invalid scope policy {}: {}", SCOPE_FILE, err);
         }
-    } else {
-        eprintln!("Either an IP address (-i or --ip) or Domain Name (-d or --domain) needs to be provided");
-        exit(1);
     };
 
-    return target;
+    for target in targets.iter_mut() {
+        let ip: IpAddr = strip_zone(&target.ip).parse().unwrap();
+        if let Err(err) = policy.check(ip) {
+            fail!(args, "scope", "Refusing to scan {}: {} in {}", target.ip, err, SCOPE_FILE);
+        }
+
+        target.fallbacks.retain(|fallback| match policy.check(fallback.parse().unwrap()) {
+            Ok(()) => true,
+            Err(err) => {
+                eprintln!("Not falling back to {}: {} in {}", fallback, err, SCOPE_FILE);
+                false
+            }
+        });
+    }
+
+    true
 }
 
-// This is what each thread will run to scan an individual
-// port
-async fn scan_port(target: String, port: u16) -> Option<u16> {
-   let address = format!("{}:{}", target, port);
+// fails the run if any target has an open port that isn't allowed,
+// once the reports are saved so the results aren't lost, listing the
+// ports that broke the policy on each target
+fn enforce_allowed_ports(args: &Args, reports: &[Report], allowed_ports: &[u16]) {
+    let violations: Vec<String> = reports
+        .iter()
+        .filter_map(|report| {
+            let ports: Vec<String> = report.open_ports.iter().filter(|result| !allowed_ports.contains(&result.port)).map(|result| result.port.to_string()).collect();
+            (!ports.is_empty()).then(|| format!("{} ({})", report.target, ports.join(", ")))
+        })
+        .collect();
 
-    let timeout = tokio::time::timeout(Duration::from_secs(2), TcpStream::connect(&address));
+    if !violations.is_empty() {
+        fail!(args, "policy", "Open ports that aren't in --allowed-ports: {}", violations.join(", "));
+    }
+}
 
-    match timeout.await {
-        Ok(Ok(_)) => Some(port),
-        _ => None,
+// stops before a scan that's far bigger than anyone would mean to
+// run, like a long targets file with every port, pairs is the
+// number of hosts times the number of ports on each
+fn enforce_expand_limit(args: &Args, pairs: u64) {
+    if pairs > args.ports_expand_limit && !args.force {
+        fail!(args, "usage", "Refusing to scan {} host and port pairs, which is more than --ports-expand-limit {} (pass --force to scan anyway)", pairs, args.ports_expand_limit);
     }
 }
 
-// This handles creating all the threads to scan each
-// port and then collect the results on which ports are open
-async fn scan_ports(target: String) -> Vec<u16> {
-    let mut tasks = Vec::new();
-    let mut open_ports = Vec::new();
+// the addresses to scan from for --source-ip, each one repeated for
+// as many turns as its weight gives it
+fn get_source_ips(args: &Args) -> Vec<IpAddr> {
+    let mut source_ips: Vec<IpAddr> = Vec::new();
 
-    // create sempahore with 1000 available spots
-    // This makes it so that only 1000 threads can
-    // run at the same time
-    let semaphore = Arc::new(Semaphore::new(1000));
+    for source_ip in &args.source_ip {
+        let (ip, weight): (&str, &str) = source_ip.split_once('=').unwrap_or((source_ip, "1"));
+        let ip: IpAddr = match ip.parse::<IpAddr>() {
+            Ok(ip) => ip.to_canonical(),
+            Err(_) => fail!(args, "usage", "Invalid --source-ip address: {}", ip),
+        };
+        let weight: usize = match weight.parse::<usize>() {
+            Ok(weight) if weight > 0 => weight,
+            _ => fail!(args, "usage", "The --source-ip weight has to be a whole number of at least 1: {}", source_ip),
+        };
 
-    for port in 1..=65535 {
-        // have to clone so each thread doesn't own
-        // target or the semaphore
-        let semaphore = Arc::clone(&semaphore);
-        let target_copy = target.clone();
+        source_ips.extend(std::iter::repeat_n(ip, weight));
+    }
+
+    source_ips
+}
+
+// refuses to scan if any --source-ip is a different family than any
+// of the targets, since an IPv4 socket can't be bound to an IPv6
+// address and every connect from it would fail
+fn check_source_ip_families(args: &Args, targets: &[Target]) {
+    let source_ips: Vec<IpAddr> = get_source_ips(args);
 
-        tasks.push(tokio::spawn(async move {
-            let permit = semaphore.acquire().await.unwrap();
-            let result = scan_port(target_copy, port).await;
-            drop(permit);
-            result
-        }));
+    for target in targets {
+        let Ok(ip) = strip_zone(&target.ip).parse::<IpAddr>().map(|ip| ip.to_canonical()) else {
+            continue;
+        };
+        if let Some(source_ip) = source_ips.iter().find(|source_ip| source_ip.is_ipv4() != ip.is_ipv4()) {
+            let family = |ip: &IpAddr| if ip.is_ipv4() { "IPv4" } else { "IPv6" };
+            fail!(args, "usage", "--source-ip {} is {} but the target {} is {}, every source address has to be the same family as the targets", source_ip, family(source_ip), target.ip, family(&ip));
+        }
     }
+}
 
-    println!("PORT  STATE  SERVICE\n");
+// the address to scan from for --interface, the interface's address
+// of the same family as the targets. Link-local targets are scanned
+// from its link-local address since nothing else can reach them, and
+// any other IPv6 target from an address that isn't link-local
+fn get_interface_ip(args: &Args, name: &str, targets: &[Target]) -> IpAddr {
+    let interfaces: Vec<if_addrs::Interface> = match if_addrs::get_if_addrs() {
+        Ok(interfaces) => interfaces,
+        Err(err) => {
+            fail!(args, "interface", "Failed to list network interfaces: {}", err);
+        }
+    };
 
-    for task in tasks {
-        if let Some(port) = task.await.unwrap() {
-            let service = get_service_by_port(port).unwrap_or("<unknown>");
+    let ips: Vec<IpAddr> = targets.iter().filter_map(|target| strip_zone(&target.ip).parse::<IpAddr>().ok()).map(|ip| ip.to_canonical()).collect();
+    let kind = |ip: &IpAddr| match ip {
+        IpAddr::V4(_) => "IPv4",
+        IpAddr::V6(ip) if ip.is_unicast_link_local() => "link-local IPv6",
+        IpAddr::V6(_) => "IPv6",
+    };
+    let wanted: &str = match ips.first().map(kind) {
+        Some(wanted) if ips.iter().all(|ip| kind(ip) == wanted) => wanted,
+        Some(_) => fail!(args, "interface", "--interface can only scan targets that are all IPv4, all link-local IPv6, or all other IPv6, since it scans them all from one of {}'s addresses", name),
+        None => "IPv4",
+    };
 
-            println!("{}  open  {}", port, service);
-            open_ports.push(port);
+    let mut found: bool = false;
+    for interface in interfaces.iter().filter(|interface| interface.name == name) {
+        found = true;
+        if kind(&interface.ip()) == wanted {
+            return interface.ip();
         }
     }
 
-    open_ports
+    if found {
+        fail!(args, "interface", "Network interface {} has no {} address to scan from", name, wanted);
+    }
+    fail!(args, "interface", "No network interface named {}", name);
 }
 
-// Writes the open ports to a file
-async fn write_output_file(open_ports: Vec<u16>, target: String, args: &Args) {
-    match File::create(args.output_filename.clone()).await {
-        Ok(mut file) => {
-            let start_line = format!("Starting BadMap {}\n", option_env!("CARGO_PKG_VERSION").unwrap_or("<uknown>"));
-            if let Err(err) = file.write_all(start_line.as_bytes()).await {
-                eprintln!("Failed to write to file: {}", err);
-            }
+// the key to log in to the jump host with, the usual OpenSSH
+// default keys are tried if one wasn't given
+fn get_ssh_key(args: &Args) -> Option<PathBuf> {
+    if let Some(key) = &args.ssh_key {
+        return Some(PathBuf::from(key));
+    }
 
-            let target_line: String;
-            if !args.domain.is_empty() {
-                target_line = format!("BadMap Scan Report for {} ({})\n\n", args.domain, target);
-            } else {
-                target_line = format!("BadMap Scan Report for {}\n\n", target);
-            }
-            if let Err(err) = file.write_all(target_line.as_bytes()).await {
-                eprintln!("Failed to write to file: {}", err);
-            }
+    let ssh_dir: PathBuf = PathBuf::from(std::env::var_os("HOME")?).join(".ssh");
+    ["id_ed25519", "id_rsa"].iter().map(|name| ssh_dir.join(name)).find(|path| path.exists())
+}
 
-            if let Err(err) = file.write_all(format!("PORT  STATE  SERVICE\n\n").as_bytes()).await {
-                eprintln!("Failed to write to file: {}", err);
-            }
+// logs in to the --ssh-jump host before anything gets scanned, so a
+// bad key or unknown host doesn't fail every port one at a time
+async fn connect_ssh_jump(args: &Args) -> Option<Arc<SshJump>> {
+    let destination: &str = args.ssh_jump.as_deref()?;
 
-            for port in open_ports {
-                // Get the service name or "unknown" if not found
-                let service = get_service_by_port(port).unwrap_or("<unknown>");
+    let Some((user, host)) = destination.split_once('@') else {
+        fail!(args, "ssh", "Invalid jump host, expected user@host or user@host:port: {}", destination);
+    };
+    // an IPv6 host needs brackets around it to have a port after it,
+    // like user@[fe80::1]:2222, and can be bare without one
+    let (host, port): (&str, Option<&str>) = match host.strip_prefix('[').and_then(|host| host.split_once(']')) {
+        Some((host, "")) => (host, None),
+        Some((host, rest)) => match rest.strip_prefix(':') {
+            Some(port) => (host, Some(port)),
+            None => fail!(args, "ssh", "Invalid jump host, expected user@host or user@host:port: {}", destination),
+        },
+        None if host.parse::<Ipv6Addr>().is_ok() => (host, None),
+        None => match host.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (host, None),
+        },
+    };
+    let port: u16 = match port.map(str::parse) {
+        Some(Ok(port)) => port,
+        Some(Err(_)) => fail!(args, "ssh", "Invalid jump host port: {}", port.unwrap()),
+        None => 22,
+    };
+    let host: String = normalize_ip(host);
 
-                let line = format!("{}  open  {}\n", port, service);
-                if let Err(err) = file.write_all(line.as_bytes()).await {
-                    eprintln!("Failed to write to file: {}", err);
-                }
-            }
+    let Some(key) = get_ssh_key(args) else {
+        fail!(args, "ssh", "No SSH key found for the jump host, pass one with --ssh-key");
+    };
 
-            println!("\nResults saved to {}", args.output_filename);
-        }
+    #[cfg(unix)]
+    let jump: Result<SshJump, String> = match args.tunnel_fd {
+        Some(fd) => SshJump::connect_stream(tunnel_stream(args, fd), user, &host, port, &key).await,
+        None => SshJump::connect(user, &host, port, &key).await,
+    };
+    #[cfg(not(unix))]
+    let jump: Result<SshJump, String> = SshJump::connect(user, &host, port, &key).await;
+
+    match jump {
+        Ok(jump) => Some(Arc::new(jump)),
         Err(err) => {
-            eprintln!("Failed to create output file: {}", err);
-            exit(1);
+            fail!(args, "ssh", "Failed to connect to jump host {}: {}", destination, err);
         }
     }
 }
 
-#[tokio::main]
-async fn main() {
-    let args: Args = Args::parse();
-    println!("Starting BadMap {}", option_env!("CARGO_PKG_VERSION").unwrap_or("<uknown>"));
+// takes over the socket --tunnel-fd names, which has to be a TCP
+// connection to the jump host that whatever started BadMap opened
+// and left open for it
+#[cfg(unix)]
+fn tunnel_stream(args: &Args, fd: i32) -> tokio::net::TcpStream {
+    use std::os::fd::FromRawFd;
 
-    let target: String = get_target(&args).await;
-    if !args.domain.is_empty() {
-        println!("BadMap Scan Report for {} ({})\n", args.domain, target);
-    } else {
-        println!("BadMap Scan Report for {}\n", target);
+    if fd < 0 {
+        fail!(args, "usage", "--tunnel-fd has to be a file descriptor, not {}", fd);
+    }
+
+    // nothing else in BadMap knows about the fd, so this is the only
+    // owner of it from here on
+    let stream: std::net::TcpStream = unsafe { std::net::TcpStream::from_raw_fd(fd) };
+
+    if let Err(err) = stream.peer_addr() {
+        fail!(args, "ssh", "--tunnel-fd {} isn't a connected TCP socket: {}", fd, err);
+    }
+    let stream: Result<tokio::net::TcpStream, std::io::Error> = stream.set_nonblocking(true).and_then(|()| tokio::net::TcpStream::from_std(stream));
+
+    match stream {
+        Ok(stream) => stream,
+        Err(err) => fail!(args, "ssh", "Failed to use --tunnel-fd {}: {}", fd, err),
+    }
+}
+
+// builds the options for the scanning engine out of the CLI
+// args, warning about any that change how the scan behaves
+fn get_scan_options(args: &Args, config: &Config, targets: &[Target], jump: Option<Arc<SshJump>>) -> ScanOptions {
+    if let Some(source_port) = args.source_port {
+        eprintln!("Warning: binding to source port {} means only one port can be scanned at a time", source_port);
     }
 
-    let open_ports: Vec<u16> = scan_ports(target.clone()).await;
+    let mut builder = ScanOptions::builder()
+        .warmup(args.warmup)
+        .banners(args.banners)
+        .graceful_close(args.graceful_close)
+        .reset_as_open(args.reset_as_open)
+        .probe_timeout(Duration::from_secs(args.probe_timeout))
+        .retries(args.retries);
 
-    write_output_file(open_ports, target.clone(), &args).await;
+    if let Some(concurrency) = args.concurrency {
+        if concurrency == 0 {
+            fail!(args, "usage", "--concurrency has to be at least 1");
+        }
+        builder = builder.concurrency(concurrency);
+    }
+    if let Some(batch_size) = args.batch_size {
+        if batch_size == 0 {
+            fail!(args, "usage", "--batch-size has to be at least 1");
+        }
+        builder = builder.batch(batch_size, Duration::from_millis(args.batch_delay));
+    }
+    if let Some(first_pass_timeout) = args.first_pass_timeout {
+        if first_pass_timeout == 0 {
+            fail!(args, "usage", "--first-pass-timeout has to be at least 1");
+        }
+        builder = builder.first_pass_timeout(Duration::from_millis(first_pass_timeout));
+    }
+    if let Some(ramp_up) = args.ramp_up {
+        builder = builder.ramp_up(Duration::from_secs(ramp_up));
+    }
+    if let Some(source_port) = args.source_port {
+        builder = builder.source_port(source_port);
+    }
+    if let Some(linger) = args.so_linger {
+        builder = builder.linger(Duration::from_secs(linger));
+    }
+    if let Some(interface) = &args.interface {
+        builder = builder.source_ip(get_interface_ip(args, interface, targets));
+    }
+    if !args.source_ip.is_empty() {
+        builder = builder.source_ips(get_source_ips(args));
+    }
+    if let Some(jump) = jump {
+        builder = builder.jump(jump);
+    }
+    if let Some(retryable) = config.retryable() {
+        builder = builder.retryable(retryable);
+    }
+    if let Some(max_retries_total) = args.max_retries_total {
+        builder = builder.max_retries_total(max_retries_total);
+    }
+    if let Some(abort_after_timeouts) = args.abort_after_timeouts {
+        if abort_after_timeouts == 0 {
+            fail!(args, "usage", "--abort-after-timeouts has to be at least 1");
+        }
+        builder = builder.abort_after_timeouts(abort_after_timeouts);
+    }
+    if let Some(timeout_scaling) = args.timeout_scaling {
+        if !(timeout_scaling > 0.0 && timeout_scaling.is_finite()) {
+            fail!(args, "usage", "--timeout-scaling has to be a positive number: {}", timeout_scaling);
+        }
+        builder = builder.timeout_scaling(timeout_scaling);
+    }
+
+    builder.build()
+}
+
+// This scans the ports on target, printing each open port as
+// it's found and any errors as requested, then collects the
+// report of the scan
+async fn scan_ports(target: String, ports: Vec<u16>, args: &Args, options: &ScanOptions, banner_regex: Option<&Regex>, cancel: &CancellationToken, notes: &mut Vec<String>) -> ScanReport {
+    status!(args, "Concurrency: {} requested, {} effective\n", options.requested_concurrency(), options.concurrency());
+    if !args.group_by_category {
+        status!(args, "PORT  STATE  SERVICE\n");
+    }
+
+    // open ports past --head are still collected, just not printed
+    let mut open: usize = 0;
+
+    // what --heartbeat reports, kept in cells since the heartbeat
+    // reads them while the scan is still adding to them
+    let total: usize = ports.len();
+    let scanned: Cell<usize> = Cell::new(0);
+    let open_total: Cell<usize> = Cell::new(0);
+    let start: Instant = Instant::now();
+
+    let scan = scan_with(&target, ports, options, cancel.clone(), |result| {
+        scanned.set(scanned.get() + 1);
+        if result.is_open() {
+            open_total.set(open_total.get() + 1);
+        }
+
+        match result.error {
+            // grouped ports can only be printed once they're all in
+            None if args.group_by_category => {}
+            None if is_reported(result, args, banner_regex) => {
+                open += 1;
+                if args.head.is_none_or(|head| open <= head) {
+                    status!(args, "{}", format_port_line(result, args.banners));
+                }
+            }
+            None => {}
+            // with --quiet-errors the errors are only summarized
+            // once the scan is done
+            Some(_) if args.quiet_errors => {}
+            // the reason is what tells a closed port from one a
+            // firewall is dropping, which is most of debugging one
+            Some(kind) if args.verbose => {
+                eprintln!("{}  {}  {}", result.port, result.state(), kind);
+            }
+            _ => {}
+        }
+    });
+
+    let report: ScanReport = match args.heartbeat.filter(|&heartbeat| heartbeat > 0) {
+        Some(heartbeat) => {
+            tokio::pin!(scan);
+            let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + Duration::from_secs(heartbeat), Duration::from_secs(heartbeat));
+
+            loop {
+                tokio::select! {
+                    report = &mut scan => break report,
+                    _ = ticks.tick() => {
+                        eprintln!("scanned {}/{}, {} open, {} elapsed", scanned.get(), total, open_total.get(), format_elapsed(start.elapsed()));
+                    }
+                }
+            }
+        }
+        None => scan.await,
+    };
+
+    if args.quiet_errors && !report.errors.is_empty() {
+        let mut summary: Vec<(&ErrorKind, &usize)> = report.errors.iter().collect();
+        summary.sort_by_key(|&(_, &count)| std::cmp::Reverse(count));
+
+        eprintln!();
+        for (kind, count) in summary {
+            eprintln!("{} ports: {}", count, kind);
+        }
+    }
+
+    if let Some(head) = args.head.filter(|&head| open > head && !args.group_by_category) {
+        status!(args, "...and {} more, see {}", open - head, args.output_filename);
+    }
+
+    status!(args, "\nPeak concurrent connections: {}", report.peak_concurrency);
+    if let Some(scaled_timeout) = report.scaled_timeout {
+        status!(args, "Connect timeout scaled to {}ms from the latency of the first connects", scaled_timeout.as_millis());
+    }
+    if args.verbose {
+        if let Some(peak_memory) = peak_memory_kb() {
+            status!(args, "Peak memory: {:.1} MiB", peak_memory as f64 / 1024.0);
+        }
+    }
+
+    if report.fd_waits > 0 {
+        eprintln!("\nWarning: ran out of file descriptors, {} ports had to wait for one to free up before they could be scanned. Lower --concurrency (scanning {} at a time now) or raise the limit with ulimit -n", report.fd_waits, options.concurrency());
+    }
+    if !report.fd_exhausted.is_empty() {
+        eprintln!("\n{} ports never got a file descriptor and weren't scanned", report.fd_exhausted.len());
+        notes.push(format!("Ran out of file descriptors, these ports weren't scanned: {}", format_port_spec(&report.fd_exhausted)));
+    }
+
+    if args.retries > 0 && options.max_retries_total().is_some_and(|max_retries_total| report.retries >= max_retries_total) {
+        eprintln!("\nUsed up the retry budget of {} retries, ports after that weren't retried", report.retries);
+    }
+
+    if report.aborted {
+        let skipped: Vec<u16> = report.untested.iter().copied().filter(|port| !report.fd_exhausted.contains(port)).collect();
+        eprintln!("\nGave up on {} after {} timeouts in a row, {} ports weren't scanned", target, args.abort_after_timeouts.unwrap_or(0), skipped.len());
+        notes.push(format!("Gave up after {} timeouts in a row, these ports weren't scanned: {}", args.abort_after_timeouts.unwrap_or(0), format_port_spec(&skipped)));
+    } else if report.cancelled {
+        eprintln!("\nScan cancelled, only partial results were collected");
+    }
+
+    report
+}
+
+// a rough elapsed time for the heartbeat, like 45s, 14m, or 2h 5m
+fn format_elapsed(elapsed: Duration) -> String {
+    let seconds: u64 = elapsed.as_secs();
+    match seconds {
+        0..60 => format!("{}s", seconds),
+        60..3600 => format!("{}m", seconds / 60),
+        _ => format!("{}h {}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
+
+// the most memory BadMap has held at once so far, in KiB, which is
+// what tells how much a bigger --concurrency or range really costs.
+// Only Linux keeps it in /proc, elsewhere there's nothing to show
+fn peak_memory_kb() -> Option<u64> {
+    let status: String = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line: &str = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+
+    line.trim_start_matches("VmHWM:").trim().trim_end_matches("kB").trim().parse().ok()
+}
+
+// whether an open port should be reported, with --banner-regex only
+// the ones that sent a matching banner are
+fn matches_banner_regex(result: &PortResult, banner_regex: Option<&Regex>) -> bool {
+    match (banner_regex, &result.banner) {
+        (None, _) => true,
+        (Some(banner_regex), Some(banner)) => banner_regex.is_match(banner),
+        (Some(_), None) => false,
+    }
+}
+
+// whether an open port should be reported at all, which is every one
+// unless it's filtered out by --banner-regex, --only-unexpected, or
+// --filter
+fn is_reported(result: &PortResult, args: &Args, banner_regex: Option<&Regex>) -> bool {
+    matches_banner_regex(result, banner_regex) && !(args.only_unexpected && get_service_by_port(result.port).is_some()) && args.parsed_filter.as_ref().is_none_or(|filter| filter.matches(result, args.banners))
+}
+
+// a scan that found nothing open while most ports couldn't even be
+// routed to probably hit a network problem rather than a closed host
+fn looks_like_network_failure(results: &[PortResult]) -> bool {
+    let unreachable = results
+        .iter()
+        .filter(|result| matches!(result.error, Some(ErrorKind::NetworkUnreachable | ErrorKind::HostUnreachable)))
+        .count();
+
+    !results.is_empty() && !results.iter().any(|result| result.is_open()) && unreachable * 2 >= results.len()
+}
+
+// works out, for every port that was open in at least one of the
+// repeated scans, how much of the time it was open and how many
+// times it changed state from one scan to the next
+fn get_repeat_summary(runs: &[Vec<u16>]) -> RepeatSummary {
+    let mut ports: Vec<u16> = runs.iter().flatten().copied().collect();
+    ports.sort_unstable();
+    ports.dedup();
+
+    let ports: Vec<PortUptime> = ports
+        .into_iter()
+        .map(|port| {
+            let states: Vec<bool> = runs.iter().map(|run| run.contains(&port)).collect();
+
+            PortUptime {
+                port,
+                uptime: states.iter().filter(|&&open| open).count() * 100 / states.len(),
+                changes: states.windows(2).filter(|pair| pair[0] != pair[1]).count(),
+            }
+        })
+        .collect();
+
+    RepeatSummary { runs: runs.len(), ports }
+}
+
+// the first of filename, filename-1, filename-2, and so on that
+// doesn't exist yet, with the number going before the extension so
+// scan.json.gz becomes scan-1.json.gz
+fn unclobbered_filename(filename: &str) -> String {
+    if !Path::new(filename).exists() {
+        return filename.to_string();
+    }
+
+    let (name, gz): (&str, &str) = match filename.strip_suffix(".gz") {
+        Some(name) => (name, ".gz"),
+        None => (filename, ""),
+    };
+    let path: &Path = Path::new(name);
+    let (stem, extension): (&str, String) = match path.extension() {
+        Some(extension) => (&name[..name.len() - extension.len() - 1], format!(".{}", extension.to_string_lossy())),
+        None => (name, String::new()),
+    };
+
+    (1..)
+        .map(|number| format!("{}-{}{}{}", stem, number, extension, gz))
+        .find(|candidate| !Path::new(candidate).exists())
+        .unwrap()
+}
+
+// creates the output file, anything ending in .gz gets written
+// through a gzip encoder so the rest of the writing doesn't have
+// to care whether it's compressed. With no_clobber a file that
+// already exists is an error instead of being truncated
+async fn create_output_file(filename: &str, no_clobber: bool) -> std::io::Result<Box<dyn AsyncWrite + Unpin + Send>> {
+    let file: Box<dyn AsyncWrite + Unpin + Send> = match open_output_stream(filename).await? {
+        Some(stream) => stream,
+        None => Box::new(File::options().write(true).create(true).create_new(no_clobber).truncate(true).open(filename).await?),
+    };
+
+    if filename.ends_with(".gz") {
+        Ok(Box::new(GzipEncoder::new(file)))
+    } else {
+        Ok(Box::new(file))
+    }
+}
+
+// opens filename for writing if it's a FIFO, socket, or device rather
+// than a regular file, None if it's a regular file or doesn't exist
+// yet. A FIFO blocks here until something opens it for reading, which
+// is on a blocking thread so nothing else is held up
+#[cfg(unix)]
+async fn open_output_stream(filename: &str) -> std::io::Result<Option<Box<dyn AsyncWrite + Unpin + Send>>> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let file_type: std::fs::FileType = match tokio::fs::metadata(filename).await {
+        Ok(metadata) if !metadata.is_file() => metadata.file_type(),
+        _ => return Ok(None),
+    };
+
+    if file_type.is_socket() {
+        return Ok(Some(Box::new(tokio::net::UnixStream::connect(filename).await?)));
+    }
+
+    Ok(Some(Box::new(File::options().write(true).open(filename).await?)))
+}
+
+// everything but unix has no FIFOs or sockets in the filesystem
+#[cfg(not(unix))]
+async fn open_output_stream(_filename: &str) -> std::io::Result<Option<Box<dyn AsyncWrite + Unpin + Send>>> {
+    Ok(None)
+}
+
+// flushes the finished report and the directory entry for it to
+// disk, the file has to be opened again since by now it may be
+// behind a gzip encoder
+async fn fsync_output_file(filename: &str) -> std::io::Result<()> {
+    File::open(filename).await?.sync_all().await?;
+
+    let path: &Path = Path::new(filename);
+    let dir: &Path = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir).await?.sync_all().await
+}
+
+// Writes the reports to the output file in the format that
+// was asked for
+async fn write_output_file(reports: &[Report], args: &Args) {
+    let template: Option<Template> = args.template.as_ref().map(|line| Template {
+        header: args.template_header.clone(),
+        line: line.clone(),
+        footer: args.template_footer.clone(),
+    });
+
+    let contents: String = if args.summary_only {
+        render_summary_only(reports, args.is_target_list())
+    } else {
+        render(reports, args.format(), template.as_ref(), args.is_target_list())
+    };
+
+    write_output(&contents, args).await;
+}
+
+// writes the rendered report out to wherever -o points
+async fn write_output(contents: &str, args: &Args) {
+    if args.report_to_stdout() {
+        let mut stdout = tokio::io::stdout();
+        if let Err(err) = stdout.write_all(contents.as_bytes()).await {
+            eprintln!("Failed to write to stdout: {}", err);
+        }
+        if let Err(err) = stdout.flush().await {
+            eprintln!("Failed to write to stdout: {}", err);
+        }
+
+        // there's no file to put a sidecar next to, so the hash goes
+        // to stderr where it won't end up in the report
+        if args.sign {
+            eprintln!("SHA-256: {}", sha256_hex(contents.as_bytes()));
+        }
+        return;
+    }
+
+    // the key gets loaded before anything is written so a bad key
+    // doesn't leave an unsigned report behind
+    let key = args.sign_key.as_ref().map(|path| match load_signing_key(path) {
+        Ok(key) => key,
+        Err(err) => {
+            fail!(args, "sign", "Failed to load signing key: {}", err);
+        }
+    });
+
+    match create_output_file(&args.output_filename, args.no_clobber).await {
+        Ok(mut file) => {
+            if let Err(err) = file.write_all(contents.as_bytes()).await {
+                eprintln!("Failed to write to file: {}", err);
+            }
+
+            // shutting down flushes everything, which for gzip
+            // also writes out the trailer
+            if let Err(err) = file.shutdown().await {
+                eprintln!("Failed to write to file: {}", err);
+            }
+
+            // a pipe or socket has nothing on disk to sync or sign, so
+            // it's treated the same as stdout
+            if args.report_to_stream() {
+                println!("\nResults written to {}", args.output_filename);
+                if args.sign {
+                    eprintln!("SHA-256: {}", sha256_hex(contents.as_bytes()));
+                }
+                return;
+            }
+
+            if args.fsync {
+                if let Err(err) = fsync_output_file(&args.output_filename).await {
+                    fail!(args, "io", "Failed to sync {} to disk: {}", args.output_filename, err);
+                }
+            }
+
+            println!("\nResults saved to {}", args.output_filename);
+
+            if args.sign {
+                match sign_output_file(&args.output_filename, key.as_ref()) {
+                    Ok(()) => println!("Checksum saved to {}.sha256", args.output_filename),
+                    Err(err) => {
+                        fail!(args, "sign", "Failed to sign {}: {}", args.output_filename, err);
+                    }
+                }
+                if args.sign_key.is_some() {
+                    println!("Signature saved to {}.sig", args.output_filename);
+                }
+            }
+        }
+        Err(err) => {
+            fail!(args, "io", "Failed to create output file: {}", err);
+        }
+    }
+}
+
+// scans one port at a time across every target for --vertical, so
+// the concurrency is spread over the hosts instead of one host
+// getting all of it, and writes the report grouped by port
+async fn run_vertical(targets: &[Target], ports: &[u16], args: &Args, options: &ScanOptions, banner_regex: Option<&Regex>, cancel: &CancellationToken) {
+    let addresses: Vec<String> = targets.iter().map(|target| target.ip.clone()).collect();
+    explain!(args, "scanning each of the {} ports across all {} targets before moving on to the next", ports.len(), targets.len());
+
+    let mut reports: Vec<PortReport> = Vec::new();
+
+    for &port in ports {
+        if cancel.is_cancelled() {
+            break;
+        }
+
+        status!(args, "\nPort {} ({})\n", port, get_service_by_port(port).unwrap_or("<unknown>"));
+
+        let mut hosts: Vec<OpenHost> = Vec::new();
+        let results: Vec<PortResult> = scan_targets_with(&addresses, port, options, cancel.clone(), |index, result| {
+            if result.is_open() && is_reported(result, args, banner_regex) {
+                let host = OpenHost { target: targets[index].ip.clone(), domain: targets[index].domain.clone(), result: result.clone() };
+                status!(args, "{}", format_open_host(&host));
+                hosts.push(host);
+            }
+        })
+        .await;
+
+        status!(args, "\nOpen on {} of {} hosts", hosts.len(), results.len());
+        reports.push(PortReport { port, hosts, scanned: results.len() });
+    }
+
+    if cancel.is_cancelled() {
+        eprintln!("\nScan cancelled, only partial results were collected");
+    }
+
+    let timestamp: Option<String> = args.output_append_timestamp.then(|| Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true));
+    write_output(&render_vertical(&reports, args.format(), timestamp.as_deref()), args).await;
+}
+
+// checks which hosts in range are up instead of scanning ports,
+// every host in it has to be allowed by the scope policy
+async fn run_sweep(range: &str, args: &Args, config: &Config, cancel: &CancellationToken) {
+    let range: Cidr = match range.parse() {
+        Ok(range) => range,
+        Err(err) => {
+            fail!(args, "usage", "Invalid sweep range: {}", err);
+        }
+    };
+
+    let hosts: Vec<Ipv4Addr> = range.hosts();
+    if hosts.is_empty() {
+        fail!(args, "usage", "Only IPv4 ranges can be swept: {}", range);
+    }
+
+    enforce_expand_limit(args, hosts.len() as u64);
+
+    let mut targets: Vec<Target> = hosts.iter().map(|&ip| Target::from_ip(IpAddr::V4(ip), None)).collect();
+    let scoped: bool = enforce_scope_policy(args, &mut targets).await;
+    check_source_ip_families(args, &targets);
+
+    let options: ScanOptions = get_scan_options(args, config, &targets, None);
+    explain!(args, "sweeping {} hosts in {}", hosts.len(), range);
+    explain!(args, "waiting up to {}s for each host, {} hosts at a time", options.timeout().as_secs_f64(), options.concurrency());
+    explain_scope(args, scoped);
+
+    let result: SweepResult = sweep(hosts, &options, cancel.clone()).await;
+    if result.method == SweepMethod::Tcp {
+        explain!(args, "opening an ICMP socket wasn't permitted so hosts were checked with TCP connects");
+    }
+
+    status!(args, "BadMap Sweep Report for {}", range);
+    if result.method == SweepMethod::Tcp {
+        status!(args, "ICMP isn't permitted, checked hosts with TCP connects to ports 80, 443, and 22 instead");
+    }
+    status!(args, "\nHOST  LATENCY\n");
+    for host in &result.hosts {
+        status!(args, "{}  {:.1}ms", host.ip, host.latency.as_secs_f64() * 1000.0);
+    }
+    status!(args, "\n{} hosts up", result.hosts.len());
+
+    if cancel.is_cancelled() {
+        eprintln!("\nSweep cancelled, only partial results were collected");
+    }
+
+    let timestamp: Option<String> = args.output_append_timestamp.then(|| Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true));
+    write_output(&render_sweep(&range, &result, args.format(), timestamp.as_deref()), args).await;
+}
+
+// says whether the targets were checked against a scope policy
+fn explain_scope(args: &Args, scoped: bool) {
+    if scoped {
+        explain!(args, "every target was allowed by the scope policy in {}", SCOPE_FILE);
+    } else {
+        explain!(args, "no scope policy was found at {} so targets weren't restricted", SCOPE_FILE);
+    }
+}
+
+// the built-in fingerprints, with any from --fingerprints tried
+// first so they can override them
+fn load_fingerprints(args: &Args) -> FingerprintDb {
+    let Some(path) = &args.fingerprints else {
+        return FingerprintDb::builtin();
+    };
+
+    let contents: String = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) => fail!(args, "io", "Failed to read fingerprint file {}: {}", path, err),
+    };
+
+    match FingerprintDb::parse(&contents) {
+        Ok(mut fingerprints) => {
+            fingerprints.extend(FingerprintDb::builtin());
+            fingerprints
+        }
+        Err(err) => fail!(args, "usage", "Invalid fingerprint file {}: {}", path, err),
+    }
+}
+
+// a domain fronted by a CDN resolves to the CDN's edge, so its
+// open ports say nothing about the origin server. This only warns,
+// the scan still goes ahead
+fn warn_cdn_targets(targets: &[Target]) {
+    for target in targets {
+        let (Some(domain), Ok(ip)) = (&target.domain, strip_zone(&target.ip).parse::<IpAddr>()) else {
+            continue;
+        };
+
+        if let Some(provider) = cdn_provider(ip) {
+            eprintln!("Warning: {} ({}) appears to be a {} CDN edge, results may not reflect the origin", domain, target.ip, provider);
+        }
+    }
+}
+
+// describes everything that was decided before the first port gets
+// scanned, for --explain
+fn explain_plan(args: &Args, options: &ScanOptions, targets: &[Target], ports: &[u16], scoped: bool) {
+    for target in targets {
+        match &target.domain {
+            Some(domain) if !args.ip.is_empty() => explain!(args, "target {} was given as an IP address, {} is only used to label it", target.ip, domain),
+            Some(domain) => explain!(args, "target {} was resolved from {} using its first IPv4 address, with {} more to fall back on", target.ip, domain, target.fallbacks.len()),
+            None => explain!(args, "target {} was given as an IP address, no lookup needed", target.ip),
+        }
+    }
+
+    match &args.profile {
+        _ if args.srv.is_some() => explain!(args, "scanning each host only on the port its SRV record gives"),
+        _ if args.monitor.is_some() && !args.has_ports() => explain!(args, "scanning only the ports that were open in the baseline {}", args.monitor.as_deref().unwrap_or("")),
+        _ if args.monitor.is_some() => explain!(args, "scanning the ports that were open in the baseline {}, plus {} ports as spot checks", args.monitor.as_deref().unwrap_or(""), ports.len()),
+        Some(profile) => explain!(args, "scanning {} ports from the {} profile", ports.len(), profile),
+        None if args.ports.is_none() && !args.all_ports && args.job_ports.is_none() => {
+            explain!(args, "scanning {} ports from the 1000 most common since no ports were given (--all-ports scans all 65535)", ports.len())
+        }
+        None => explain!(args, "scanning {} ports", ports.len()),
+    }
+    explain!(args, "waiting up to {}s for each connect", options.timeout().as_secs_f64());
+    if let Some(first_pass_timeout) = args.first_pass_timeout {
+        explain!(args, "checking every port with a {}ms connect first, and only scanning the ones that didn't refuse again to confirm them", first_pass_timeout);
+    }
+    if let Some(timeout_scaling) = args.timeout_scaling {
+        explain!(args, "waiting {} times the median latency of the first {} connects that answer instead, once they have", timeout_scaling, TIMEOUT_SCALING_SAMPLES);
+    }
+    if let Some(abort_after_timeouts) = args.abort_after_timeouts {
+        explain!(args, "giving up on a target once {} ports in a row time out", abort_after_timeouts);
+    }
+    if let Some(interface) = &args.interface {
+        explain!(args, "connecting from {} on {}", get_interface_ip(args, interface, targets), interface);
+    }
+    if args.source_ip.len() > 1 {
+        explain!(args, "taking turns connecting from {}", args.source_ip.join(", "));
+    } else if let Some(source_ip) = args.source_ip.first() {
+        explain!(args, "connecting from {}", source_ip);
+    }
+    if let Some(ssh_jump) = &args.ssh_jump {
+        explain!(args, "connecting through the jump host {}, a port counts as open if it can open a channel to it", ssh_jump);
+    }
+    if args.source_port.is_some() {
+        explain!(args, "scanning 1 port at a time since every connect has to come from the same source port");
+    } else if args.ssh_jump.is_some() {
+        explain!(args, "scanning up to {} ports at a time, which is as many channels as we'll ask a jump host for", options.concurrency());
+    } else {
+        explain!(args, "scanning up to {} ports at a time, with no rate limit beyond that", options.concurrency());
+    }
+    if let Some(ramp_up) = args.ramp_up.filter(|&ramp_up| ramp_up > 0 && options.concurrency() > 1) {
+        explain!(args, "starting each target at {} ports at a time and growing to {} over {}s", options.concurrency().div_ceil(20), options.concurrency(), ramp_up);
+    }
+    if let Some(batch_size) = args.batch_size {
+        explain!(args, "starting connects {} at a time with a {}ms pause after each batch", batch_size, args.batch_delay);
+    }
+    if let Some(hosts) = args.max_hosts_concurrent.filter(|&hosts| hosts > 1 && targets.len() > 1) {
+        explain!(args, "scanning up to {} targets at a time, so up to {} connects at once across all of them", hosts, hosts.min(targets.len()) * options.concurrency());
+    }
+    if targets.len() > 1 && args.inter_host_delay > 0 {
+        explain!(args, "waiting {}s between starting targets", args.inter_host_delay);
+    }
+    if args.banners {
+        explain!(args, "waiting up to {}s after connecting for each banner", args.probe_timeout);
+    }
+    if args.retries > 0 {
+        explain!(args, "retrying a port up to {} times if it fails with a retryable error", args.retries);
+        if let Some(max_retries_total) = args.max_retries_total {
+            explain!(args, "making no more than {} retries in total for each target", max_retries_total);
+        }
+    }
+    explain_scope(args, scoped);
+}
+
+// scans every port on a single target, as many times as was asked
+// for, and puts together its report
+#[allow(clippy::too_many_arguments)]
+async fn scan_target(target: &Target, ports: &[u16], notes: &[String], args: &Args, config: &Config, options: &ScanOptions, banner_regex: Option<&Regex>, fingerprints: &FingerprintDb, cancel: &CancellationToken) -> Report {
+    let start: Instant = Instant::now();
+
+    // the cache keeps every open port and the filters are applied
+    // after loading it, so they don't need to be part of the key
+    let key: String = cache_key(&target.ip, ports, options, &args.repeat.to_string());
+    let cached: Option<(Duration, Vec<PortResult>)> = args.cache_ttl.and_then(|ttl| load_cached(&key, Duration::from_secs(ttl)));
+
+    let mut notes: Vec<String> = notes.to_vec();
+    if let Some((age, _)) = &cached {
+        notes.push(format!("Results are cached from the same scan {} seconds ago", age.as_secs()));
+        explain!(args, "skipping the scan of {} since the same scan finished {} seconds ago, within --cache-ttl", target.ip, age.as_secs());
+    }
+
+    match &target.domain {
+        _ if args.no_banner => {}
+        Some(domain) => status!(args, "BadMap Scan Report for {} ({})", domain, target.ip),
+        None => status!(args, "BadMap Scan Report for {}", target.ip),
+    }
+    for note in &notes {
+        status!(args, "{}", note);
+    }
+    status!(args);
+
+    // the address actually being scanned, which moves on to the
+    // domain's other addresses if the first one is unreachable
+    let mut ip: String = target.ip.clone();
+
+    let mut open_ports: Vec<PortResult> = Vec::new();
+    let mut checked_ports: Vec<u16> = Vec::new();
+    let mut runs: Vec<Vec<u16>> = Vec::new();
+
+    // every open port of the last run, before anything was left out of
+    // the report, which is what gets cached
+    let mut scanned_open: Vec<PortResult> = Vec::new();
+
+    // whether the last run scanned every port, a run that left some
+    // out would read as those ports not being open if it was cached
+    let mut complete: bool = true;
+
+    // cached scans only kept the open ports, so there's nothing to
+    // count for them
+    let mut state_counts: Option<StateCounts> = None;
+
+    // a cached scan stands in for every run
+    let repeat: u32 = if cached.is_some() { 0 } else { args.repeat.max(1) };
+    if let Some((_, cached_ports)) = cached {
+        let (cached_ports, hidden): (Vec<PortResult>, Vec<PortResult>) = cached_ports.into_iter().partition(|result| is_reported(result, args, banner_regex));
+        if !hidden.is_empty() {
+            explain!(args, "left out {} cached open ports because of --banner-regex, --only-unexpected, or --filter", hidden.len());
+        }
+
+        if !args.group_by_category {
+            status!(args, "PORT  STATE  SERVICE\n");
+            for result in cached_ports.iter().take(args.head.unwrap_or(usize::MAX)) {
+                status!(args, "{}", format_port_line(result, args.banners));
+            }
+            if let Some(head) = args.head.filter(|&head| cached_ports.len() > head) {
+                status!(args, "...and {} more, see {}", cached_ports.len() - head, args.output_filename);
+            }
+        }
+        open_ports = cached_ports;
+
+        // only complete scans get cached, and like a fresh scan an
+        // open port that was left out isn't known to be anything
+        checked_ports = ports.iter().copied().filter(|port| !hidden.iter().any(|result| result.port == *port)).collect();
+    }
+
+    for run in 1..=repeat {
+        if run > 1 {
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(args.interval)) => {}
+                _ = cancel.cancelled() => break,
+            }
+            status!(args, "\nScan {} of {}\n", run, args.repeat);
+        }
+
+        if args.warmup && run == 1 {
+            explain!(args, "making a few throwaway connects to {} first so caches are warm", ip);
+        }
+
+        let mut report: ScanReport = scan_ports(ip.clone(), ports.to_vec(), args, options, banner_regex, cancel, &mut notes).await;
+
+        if !args.retry_scan && looks_like_network_failure(&report.results) {
+            explain!(args, "nothing was open and most ports were unreachable, which looks like a network problem (--retry-scan would try again)");
+        }
+
+        if args.retry_scan && !cancel.is_cancelled() && looks_like_network_failure(&report.results) {
+            eprintln!("\nNothing was open and most ports were unreachable, retrying the scan in {} seconds\n", RETRY_SCAN_DELAY.as_secs());
+
+            tokio::select! {
+                _ = tokio::time::sleep(RETRY_SCAN_DELAY) => {
+                    report = scan_ports(ip.clone(), ports.to_vec(), args, options, banner_regex, cancel, &mut notes).await;
+                }
+                _ = cancel.cancelled() => {}
+            }
+        }
+
+        // only the first run picks the address, later runs stay on
+        // whichever one answered
+        if run == 1 {
+            for fallback in &target.fallbacks {
+                if cancel.is_cancelled() || !looks_like_network_failure(&report.results) {
+                    break;
+                }
+
+                status!(args, "\n{} looks unreachable, trying {} which {} also resolved to\n", ip, fallback, target.domain.as_deref().unwrap_or(""));
+                ip = fallback.clone();
+                report = scan_ports(ip.clone(), ports.to_vec(), args, options, banner_regex, cancel, &mut notes).await;
+            }
+        }
+
+        // untested also has the ports that never got a file descriptor
+        complete = !report.aborted && report.untested.is_empty();
+        let results: Vec<PortResult> = report.results;
+
+        if let Some(banner_regex) = banner_regex {
+            let hidden: usize = results.iter().filter(|result| result.is_open() && !matches_banner_regex(result, Some(banner_regex))).count();
+            if hidden > 0 {
+                explain!(args, "left out {} open ports whose banner didn't match {}", hidden, banner_regex);
+            }
+        }
+
+        if args.only_unexpected {
+            let expected: usize = results.iter().filter(|result| result.is_open() && get_service_by_port(result.port).is_some()).count();
+            if expected > 0 {
+                explain!(args, "left out {} open ports on well-known service ports because of --only-unexpected", expected);
+            }
+        }
+
+        if let Some(filter) = &args.parsed_filter {
+            let hidden: usize = results.iter().filter(|result| result.is_open() && !filter.matches(result, args.banners)).count();
+            if hidden > 0 {
+                explain!(args, "left out {} open ports that didn't match --filter", hidden);
+            }
+        }
+
+        if args.report_closed_count {
+            state_counts = Some(StateCounts::count(&results));
+        }
+
+        // an open port that was left out isn't known to be anything
+        scanned_open = results.iter().filter(|result| result.is_open()).cloned().collect();
+        checked_ports = results.iter().filter(|result| !result.is_open() || is_reported(result, args, banner_regex)).map(|result| result.port).collect();
+        open_ports = results.into_iter().filter(|result| result.is_open() && is_reported(result, args, banner_regex)).collect();
+        runs.push(open_ports.iter().map(|result| result.port).collect());
+
+        if cancel.is_cancelled() {
+            explain!(args, "stopping the repeated scans early since the scan was cancelled");
+            break;
+        }
+    }
+
+    // only a complete scan is worth reusing
+    if args.cache_ttl.is_some() && repeat > 0 && complete && !cancel.is_cancelled() {
+        if let Err(err) = cache::store(&key, &scanned_open) {
+            eprintln!("Failed to cache scan results: {}", err);
+        }
+    }
+
+    sort_open_ports(&mut open_ports, args.output_sort, args.banners);
+    if args.group_by_category {
+        status!(args, "\nPORT  STATE  SERVICE\n\n{}", format_grouped_ports(&open_ports, args.banners).trim_end());
+    }
+
+    let http_checks: HashMap<u16, HttpCheck> = match &args.http_check {
+        Some(path) => {
+            let web_ports: Vec<(u16, bool)> = open_ports
+                .iter()
+                .filter_map(|result| match get_service(result, args.banners).map(|(name, _)| name) {
+                    Some("HTTP") => Some((result.port, false)),
+                    Some("HTTPS") => Some((result.port, true)),
+                    _ => None,
+                })
+                .collect();
+
+            explain!(args, "asking {} open web ports for {} because of --http-check", web_ports.len(), path);
+            let http_checks: HashMap<u16, HttpCheck> = check_http(&ip, target.domain.as_deref(), &web_ports, path, Duration::from_secs(args.probe_timeout)).await;
+            if !http_checks.is_empty() {
+                status!(args, "\n{}", format_http_checks(path, &http_checks).trim_end());
+            }
+            http_checks
+        }
+        None => HashMap::new(),
+    };
+
+    let open_port_numbers: Vec<u16> = open_ports.iter().map(|result| result.port).collect();
+    let exposure_score: u32 = exposure_score(&open_port_numbers, &config.exposure_weights());
+    if let Some(counts) = &state_counts {
+        status!(args, "\n{}", format_state_counts(counts));
+    }
+
+    let firewall_probe: Option<FirewallProbe> = match args.firewall_probe {
+        Some(count) if !cancel.is_cancelled() => probe_firewall(&ip, ports, count, args, options, cancel).await,
+        _ => None,
+    };
+    if let Some(probe) = &firewall_probe {
+        status!(args, "\n{}", format_firewall_probe(probe));
+    }
+    status!(args, "\nExposure score: {}", exposure_score);
+
+    let summary: Option<RepeatSummary> = if args.repeat > 1 && repeat > 0 {
+        let summary = get_repeat_summary(&runs);
+        status!(args, "\n{}", format_summary(&summary).trim_end());
+        Some(summary)
+    } else {
+        None
+    };
+
+    let identified: HashMap<u16, Fingerprint> = open_ports
+        .iter()
+        .filter_map(|result| Some((result.port, fingerprints.identify(result.banner.as_deref()?)?)))
+        .collect();
+
+    Report {
+        version: option_env!("CARGO_PKG_VERSION").unwrap_or("<uknown>"),
+        timestamp: args.output_append_timestamp.then(|| Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)),
+        command: args.output_include_command.then(invocation),
+        target: ip,
+        domain: target.domain.clone(),
+        header: !args.no_banner,
+        notes,
+        banners: args.banners,
+        fingerprints: identified,
+        http_check: args.http_check.clone(),
+        http_checks,
+        open_ports,
+        checked_ports,
+        grouped: args.group_by_category,
+        state_counts,
+        firewall_probe,
+        closed_since_baseline: None,
+        exposure_score,
+        summary,
+        duration: start.elapsed(),
+    }
+}
+
+// the ports --firewall-probe picks from, the dynamic range that
+// services almost never listen on
+const FIREWALL_PROBE_PORTS: std::ops::RangeInclusive<u16> = 49152..=65535;
+
+// connects to count random ports in FIREWALL_PROBE_PORTS that weren't
+// part of the scan, for --firewall-probe. A host that resets them is
+// rejecting what it doesn't serve and one that lets them time out is
+// behind something dropping it
+async fn probe_firewall(ip: &str, ports: &[u16], count: usize, args: &Args, options: &ScanOptions, cancel: &CancellationToken) -> Option<FirewallProbe> {
+    let scanned: HashSet<u16> = ports.iter().copied().collect();
+    let candidates: Vec<u16> = FIREWALL_PROBE_PORTS.filter(|port| !scanned.contains(port)).collect();
+    if candidates.is_empty() {
+        explain!(args, "skipping --firewall-probe since every port it samples from was already scanned");
+        return None;
+    }
+
+    let sample: Vec<u16> = sample_ports(candidates, count, args.seed.unwrap_or_else(rand::random));
+    explain!(args, "connecting to {} high ports on {} that should be closed to see how its firewall answers: {}", sample.len(), ip, format_port_spec(&sample));
+
+    let report: ScanReport = scan(ip, sample, options, cancel.clone()).await;
+    Some(FirewallProbe::count(&report.results))
+}
+
+// scans target for --monitor, only checking the ports that were open
+// on it in the baseline along with any ports that were asked for on
+// top as spot checks, and notes which of the baseline's have closed
+#[allow(clippy::too_many_arguments)]
+async fn monitor_target(target: &Target, baseline: &[Baseline], ports: &[u16], notes: &[String], args: &Args, config: &Config, options: &ScanOptions, banner_regex: Option<&Regex>, fingerprints: &FingerprintDb, cancel: &CancellationToken) -> Report {
+    let expected: &[u16] = baseline.iter().find(|baseline| baseline.target == normalize_ip(&target.ip)).map_or(&[], |baseline| &baseline.ports);
+    if expected.is_empty() {
+        eprintln!("Nothing was open on {} in {}, only scanning the ports that were asked for", target.ip, args.monitor.as_deref().unwrap_or(""));
+    }
+
+    let spot_checks: &[u16] = if args.has_ports() { ports } else { &[] };
+    let mut monitored: Vec<u16> = expected.to_vec();
+    monitored.extend(spot_checks.iter().filter(|port| !expected.contains(port)));
+    explain!(args, "monitoring the {} ports that were open on {} in the baseline, plus {} spot checks", expected.len(), target.ip, monitored.len() - expected.len());
+
+    let mut report: Report = scan_target(target, &monitored, notes, args, config, options, banner_regex, fingerprints, cancel).await;
+
+    let closed: Vec<u16> = expected.iter().copied().filter(|&port| !report.open_ports.iter().any(|result| result.port == port)).collect();
+    if !expected.is_empty() {
+        status!(args, "\n{}", format_closed_since_baseline(&closed, expected.len()));
+    }
+    report.closed_since_baseline = Some(closed);
+
+    report
+}
+
+// $XDG_STATE_HOME/badmap, falling back on ~/.local/state
+fn state_dir() -> Option<PathBuf> {
+    let state_dir: PathBuf = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".local").join("state"),
+    };
+
+    Some(state_dir.join("badmap"))
+}
+
+// where the report goes without --output-filename, a file named for
+// when the scan started in the state directory so reports don't pile
+// up wherever badmap happens to be run. Without a home directory it's
+// output.txt like it used to be
+fn default_output_filename(args: &Args) -> String {
+    let extension: &str = match args.format {
+        Some(Format::Json) => "json",
+        Some(Format::Csv) => "csv",
+        Some(Format::Text) | None => "txt",
+    };
+    let filename: String = format!("scan-{}.{}", Utc::now().format("%Y%m%dT%H%M%SZ"), extension);
+
+    let Some(dir) = state_dir() else {
+        return format!("output.{}", extension);
+    };
+    if let Err(err) = std::fs::create_dir_all(&dir) {
+        fail!(args, "io", "Failed to create {}: {}", dir.display(), err);
+    }
+
+    dir.join(filename).to_string_lossy().into_owned()
+}
+
+// where the scan flags start in command_line, after scan if it was
+// given. Flags can't come before a subcommand so it's always first
+fn scan_flags_start(command_line: &[String]) -> usize {
+    if command_line.get(1).is_some_and(|arg| arg == "scan") {
+        2
+    } else {
+        1
+    }
+}
+
+// the subcommand in command_line if it isn't a scan, along with the
+// scan flags from badmap scan or from being given on their own
+fn parse_command_line(command_line: &[String]) -> (Option<Command>, Args) {
+    let cli: Cli = Cli::parse_from(command_line);
+    match cli.command {
+        Some(Command::Scan(args)) => (None, *args),
+        command => (command, cli.args),
+    }
+}
+
+// parses the command line, and if there's a --job parses it again
+// with the job's flags in front so anything on the command line
+// still overrides the job. Gives back the subcommand too when it's
+// something other than a scan
+fn parse_args() -> (Option<Command>, Args) {
+    let mut command_line: Vec<String> = std::env::args().collect();
+    let (command, mut args): (Option<Command>, Args) = parse_command_line(&command_line);
+    if command.is_some() {
+        return (command, args);
+    }
+
+    let start: usize = scan_flags_start(&command_line);
+    if start == 1 && command_line.len() > 1 {
+        eprintln!("Warning: scanning without the scan subcommand is deprecated and will stop working in a future release, run badmap scan {} instead", command_line[1..].join(" "));
+    }
+
+    let job: Option<Job> = match args.job.as_deref().map(load_job).transpose() {
+        Ok(job) => job,
+        Err(err) => fail!(args, "job", "{}", err),
+    };
+    if let Some(job) = &job {
+        let flags: Vec<String> = match job.to_args() {
+            Ok(flags) => flags,
+            Err(err) => fail!(args, "job", "{}", err),
+        };
+        command_line.splice(start..start, flags);
+        args = parse_command_line(&command_line).1;
+    }
+
+    // a profile that isn't built in has to be one saved with
+    // --save-profile, its flags go where --profile was so anything
+    // given after it still overrides them
+    let mut saved_profile: Option<String> = None;
+    if let Some(profile) = args.profile.clone().filter(|profile| get_ports_by_profile(profile).is_none()) {
+        let config: Config = match load_config(args.config.as_deref()) {
+            Ok(config) => config,
+            Err(err) => fail!(args, "config", "{}", err),
+        };
+
+        if let Some(flags) = config.profile(&profile) {
+            let position: usize = remove_flag(&mut command_line, "profile", None).unwrap_or(start);
+            command_line.splice(position..position, flags);
+            args = parse_command_line(&command_line).1;
+            saved_profile = Some(profile);
+        }
+    }
+
+    if let Some(job) = job {
+        args.job_targets = job.targets;
+        args.job_ports = job.ports;
+    }
+    args.command_line = command_line;
+    args.saved_profile = saved_profile;
+    (None, args)
+}
+
+// the command line badmap was run with, quoted so it can be pasted
+// back into a shell to run the same scan again
+fn invocation() -> String {
+    let quote = |arg: String| -> String {
+        if !arg.is_empty() && arg.chars().all(|c| c.is_ascii_alphanumeric() || "_-./:,=@%+".contains(c)) {
+            arg
+        } else {
+            format!("'{}'", arg.replace('\'', "'\\''"))
+        }
+    };
+
+    std::env::args().map(quote).collect::<Vec<String>>().join(" ")
+}
+
+// takes every use of a flag that has a value out of command_line, in
+// any of the ways it can be written, giving back where the last one
+// was
+fn remove_flag(command_line: &mut Vec<String>, long: &str, short: Option<char>) -> Option<usize> {
+    let long_flag: String = format!("--{}", long);
+    let short_flag: Option<String> = short.map(|short| format!("-{}", short));
+    let mut removed: Option<usize> = None;
+
+    let mut i: usize = 1;
+    while i < command_line.len() {
+        let arg: &str = &command_line[i];
+        let length: usize = if arg == long_flag || short_flag.as_deref() == Some(arg) {
+            2
+        } else if arg.starts_with(&format!("{}=", long_flag)) || short_flag.as_deref().is_some_and(|short_flag| arg.starts_with(short_flag) && !arg.starts_with("--")) {
+            1
+        } else {
+            i += 1;
+            continue;
+        };
+
+        command_line.drain(i..(i + length).min(command_line.len()));
+        removed = Some(i);
+    }
+
+    removed
+}
+
+// the flags that pick what gets scanned and where the report goes,
+// which change from run to run and so aren't saved in a profile
+const UNSAVED_FLAGS: &[(&str, Option<char>)] = &[
+    ("ip", Some('i')),
+    ("domain", Some('d')),
+    ("targets-file", Some('t')),
+    ("srv", None),
+    ("sweep", None),
+    ("monitor", None),
+    ("output-filename", Some('o')),
+    ("config", Some('c')),
+    ("job", None),
+    ("save-profile", None),
+];
+
+// saves the flags this run was given as the profile name, see
+// --save-profile
+fn save_current_profile(args: &Args, name: &str) {
+    if get_ports_by_profile(name).is_some() {
+        fail!(args, "usage", "{} is a built-in profile, pick another name to save under", name);
+    }
+
+    let mut command_line: Vec<String> = args.command_line.clone();
+    for &(long, short) in UNSAVED_FLAGS {
+        remove_flag(&mut command_line, long, short);
+    }
+    let start: usize = scan_flags_start(&command_line);
+    let flags: Vec<String> = command_line.into_iter().skip(start).collect();
+
+    match save_profile(args.config.as_deref(), name, &flags) {
+        Ok(path) => status!(args, "Saved profile {} to {}: {}", name, path.display(), if flags.is_empty() { "(no flags)".to_string() } else { flags.join(" ") }),
+        Err(err) => fail!(args, "config", "{}", err),
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let (command, mut args): (Option<Command>, Args) = parse_args();
+    match command {
+        Some(Command::Diff { old, new }) => {
+            run_diff(&old, &new).await;
+            return;
+        }
+        Some(Command::ListServices) => {
+            list_services();
+            return;
+        }
+        Some(Command::Scan(_)) | None => {}
+    }
+    if args.list_services {
+        list_services();
+        return;
+    }
+    if args.capabilities {
+        print_capabilities();
+        return;
+    }
+    if args.resolve_only {
+        print_resolutions(&args).await;
+        return;
+    }
+    if args.output_filename.is_empty() {
+        args.output_filename = default_output_filename(&args);
+    }
+    if args.no_clobber && !args.report_to_stdout() && !args.report_to_stream() {
+        let filename: String = unclobbered_filename(&args.output_filename);
+        if filename != args.output_filename {
+            status!(args, "{} already exists, saving to {} instead", args.output_filename, filename);
+            args.output_filename = filename;
+        }
+    }
+
+    let config: Config = match load_config(args.config.as_deref()) {
+        Ok(config) => config,
+        Err(err) => fail!(args, "config", "{}", err),
+    };
+
+    if let Some(profile) = &args.saved_profile {
+        explain!(args, "using the flags saved as profile {}", profile);
+    }
+    if let Some(name) = &args.save_profile {
+        save_current_profile(&args, name);
+
+        // saving a profile doesn't need a scan to go with it
+        if !args.has_targets() && args.monitor.is_none() && args.sweep.is_none() {
+            return;
+        }
+    }
+
+    // the regex gets checked up front so a typo doesn't waste a scan
+    let banner_regex: Option<Regex> = args.banner_regex.as_ref().map(|pattern| match Regex::new(pattern) {
+        Ok(banner_regex) => banner_regex,
+        Err(err) => {
+            fail!(args, "usage", "Invalid banner regex: {}", err);
+        }
+    });
+
+    if let Some(expression) = &args.filter {
+        match Filter::parse(expression) {
+            Ok(filter) => args.parsed_filter = Some(filter),
+            Err(err) => fail!(args, "usage", "Invalid filter {}: {}", expression, err),
+        }
+    }
+
+    if args.firewall_probe == Some(0) {
+        fail!(args, "usage", "--firewall-probe has to be at least 1");
+    }
+
+    if let Some(path) = args.http_check.as_ref().filter(|path| !path.starts_with('/')) {
+        fail!(args, "usage", "The --http-check path has to start with /: {}", path);
+    }
+
+    // checked before scanning so a typo in the policy doesn't only
+    // show up once the scan is done
+    let allowed_ports: Option<Vec<u16>> = args.allowed_ports.as_ref().map(|spec| match parse_port_spec(spec) {
+        Ok(allowed_ports) => allowed_ports,
+        Err(err) => fail!(args, "usage", "Invalid port spec {}: {}", spec, err),
+    });
+
+    let fingerprints: FingerprintDb = load_fingerprints(&args);
+
+    if !args.no_banner {
+        status!(args, "Starting BadMap {}", option_env!("CARGO_PKG_VERSION").unwrap_or("<uknown>"));
+    }
+
+    // cancel the scan on Ctrl-C so what was found so far
+    // still gets saved
+    let cancel = CancellationToken::new();
+    let ctrl_c_cancel = cancel.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            ctrl_c_cancel.cancel();
+        }
+    });
+
+    if let Some(range) = &args.sweep {
+        run_sweep(range, &args, &config, &cancel).await;
+        return;
+    }
+
+    let baseline: Option<Vec<Baseline>> = match &args.monitor {
+        Some(path) => match load_baseline(path).await {
+            Ok(baseline) => Some(baseline),
+            Err(err) => fail!(args, "target", "{}", err),
+        },
+        None => None,
+    };
+
+    let mut targets: Vec<Target> = match &baseline {
+        Some(baseline) if !args.has_targets() => get_baseline_targets(&args, baseline),
+        _ => get_targets(&args).await,
+    };
+    let scoped: bool = enforce_scope_policy(&args, &mut targets).await;
+    check_source_ip_families(&args, &targets);
+    warn_cdn_targets(&targets);
+
+    // going through a subnet in order is an obvious sweep, and it
+    // also puts any slow neighbors back to back
+    if args.randomize_targets {
+        let seed: u64 = args.seed.unwrap_or_else(rand::random);
+        shuffle_targets(&mut targets, seed);
+        explain!(args, "scanning the {} targets in a random order with seed {}", targets.len(), seed);
+    }
+
+    // an SRV record already says which port to scan, these are only
+    // all of them together for the plan
+    let mut ports: Vec<u16> = if args.srv.is_some() {
+        let mut ports: Vec<u16> = targets.iter().filter_map(|target| target.port).collect();
+        ports.sort_unstable();
+        ports.dedup();
+        ports
+    } else {
+        get_ports(&args)
+    };
+    let mut notes: Vec<String> = Vec::new();
+
+    if let Some(count) = args.sample_ports {
+        let seed: u64 = args.seed.unwrap_or_else(rand::random);
+
+        notes.push(format!("Results are a random sample of {} out of {} ports (seed {})", count.min(ports.len()), ports.len(), seed));
+        ports = sample_ports(ports, count, seed);
+        explain!(args, "randomly sampled {} ports with seed {}", ports.len(), seed);
+    }
+
+    // reversing comes last so it applies to whatever ports were
+    // picked, a sample is scanned from its highest port down too
+    if args.reverse {
+        ports.reverse();
+        explain!(args, "scanning the ports from highest to lowest");
+    }
+
+    // priority goes on top of everything else, even a reversed scan
+    // starts with the ports that matter most
+    if let Some(spec) = &args.priority_ports {
+        let priority: Vec<u16> = match parse_port_spec(spec) {
+            Ok(priority) => priority,
+            Err(err) => fail!(args, "usage", "Invalid port spec {}: {}", spec, err),
+        };
+
+        ports = prioritize_ports(ports, &priority);
+        explain!(args, "scanning {} first because of --priority-ports", spec);
+    }
+
+    enforce_expand_limit(&args, targets.len() as u64 * ports.len() as u64);
+
+    let options: ScanOptions = get_scan_options(&args, &config, &targets, connect_ssh_jump(&args).await);
+    explain_plan(&args, &options, &targets, &ports, scoped);
+
+    if args.vertical {
+        run_vertical(&targets, &ports, &args, &options, banner_regex.as_ref(), &cancel).await;
+        return;
+    }
+
+    let max_hosts_concurrent: usize = args.max_hosts_concurrent.unwrap_or(1);
+    if max_hosts_concurrent == 0 {
+        fail!(args, "usage", "--max-hosts-concurrent has to be at least 1");
+    }
+
+    // the delay and the blank line come before each target is started,
+    // one at a time, and up to max_hosts_concurrent targets are scanned
+    // at once after that. The reports still come back in target order
+    let reports: Vec<Report> = {
+        // the stream's closures only need to borrow these
+        let (args, config, options, notes, ports, fingerprints, banner_regex, baseline, cancel) = (&args, &config, &options, &notes, &ports, &fingerprints, banner_regex.as_ref(), &baseline, &cancel);
+        stream::iter(targets.iter().enumerate())
+            .then(|(index, target)| async move {
+                if index > 0 {
+                    // spaces targets out so a long list doesn't trip rate
+                    // limits on a shared network
+                    if args.inter_host_delay > 0 {
+                        tokio::select! {
+                            _ = tokio::time::sleep(Duration::from_secs(args.inter_host_delay)) => {}
+                            _ = cancel.cancelled() => {}
+                        }
+                    }
+                    status!(args);
+                }
+                target
+            })
+            .take_while(|_| future::ready(!cancel.is_cancelled()))
+            .map(|target| async move {
+                let ports: &[u16] = match &target.port {
+                    Some(port) => std::slice::from_ref(port),
+                    None => ports,
+                };
+
+                match baseline {
+                    Some(baseline) => monitor_target(target, baseline, ports, notes, args, config, options, banner_regex, fingerprints, cancel).await,
+                    None => scan_target(target, ports, notes, args, config, options, banner_regex, fingerprints, cancel).await,
+                }
+            })
+            .buffered(max_hosts_concurrent)
+            .collect()
+            .await
+    };
+
+    write_output_file(&reports, &args).await;
+
+    if let Some(path) = &args.sqlite {
+        let timestamp: String = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+        let command_line: String = std::env::args().collect::<Vec<String>>().join(" ");
+
+        match write_sqlite(path, &reports, &timestamp, &command_line) {
+            Ok(()) => status!(args, "Scan recorded in {}", path),
+            Err(err) => {
+                fail!(args, "io", "Failed to record scan in {}: {}", path, err);
+            }
+        }
+    }
+
+    if let Some(path) = &args.changelog {
+        let timestamp: String = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+
+        match append_changes(path, &reports, baseline.as_deref(), &timestamp) {
+            Ok(0) => status!(args, "Nothing changed since the last entry in {}", path),
+            Ok(changes) => status!(args, "Logged {} changes to {}", changes, path),
+            Err(err) => {
+                fail!(args, "io", "{}", err);
+            }
+        }
+    }
+
+    if let Some(path) = &args.export_inventory {
+        match write_inventory(path, &reports, args.inventory_format) {
+            Ok(services) => status!(args, "Exported {} services to {}", services, path),
+            Err(err) => {
+                fail!(args, "io", "{}", err);
+            }
+        }
+    }
+
+    if let Some(allowed_ports) = &allowed_ports {
+        enforce_allowed_ports(&args, &reports, allowed_ports);
+    }
 }
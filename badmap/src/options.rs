@@ -0,0 +1,349 @@
+use crate::ssh::SshJump;
+use crate::transport::{TcpTransport, Transport};
+
+use std::io::ErrorKind;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+// the most channels we'll have opening through a jump host at once,
+// bastions tend to start refusing well before a direct scan would
+const SSH_JUMP_CONCURRENCY: usize = 32;
+
+// errors that can go away if the connect is just tried again, anything
+// else is taken as the real answer for the port. A refused connection
+// in particular always means closed
+pub const DEFAULT_RETRYABLE: &[ErrorKind] = &[ErrorKind::TimedOut, ErrorKind::WouldBlock, ErrorKind::ConnectionReset, ErrorKind::Interrupted];
+
+// how many connects have to answer before timeout scaling picks a
+// timeout from their latency
+pub const TIMEOUT_SCALING_SAMPLES: usize = 5;
+
+// options that control how a scan is run, the fields are private
+// so new options can be added without breaking anyone, use
+// ScanOptions::builder() or ScanOptions::default() to make one
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    // how long to wait on a single connect before giving up
+    pub(crate) timeout: Duration,
+
+    // how many ports can be scanned at the same time
+    pub(crate) concurrency: usize,
+
+    // do a few throwaway connects before the real scan so the
+    // resolver, ARP, and route caches are already warm
+    pub(crate) warmup: bool,
+
+    // bind every connection to this local port, since the same
+    // source port can't be used twice at once this also limits
+    // the scan to one port at a time
+    pub(crate) source_port: Option<u16>,
+
+    // bind each connection to one of these local addresses, taking
+    // them in turn, to scan from a particular interface or to spread
+    // the connections over the ephemeral ports of several addresses
+    pub(crate) source_ips: Vec<IpAddr>,
+
+    // which of source_ips the next connection takes, shared between
+    // clones so every scan with these options keeps the same turns
+    pub(crate) next_source_ip: Arc<AtomicUsize>,
+
+    // SO_LINGER for the scan sockets, Some(Duration::ZERO) makes
+    // closing send a RST right away instead of going through
+    // the normal FIN teardown
+    pub(crate) linger: Option<Duration>,
+
+    // after connecting, send a FIN and read until the target closes
+    // its side too, since closing with a banner still unread makes
+    // the kernel send a RST that services like SMTP log as rude
+    pub(crate) graceful_close: bool,
+
+    // watch each accepted connection for a reset right after the
+    // handshake and count those ports as open|reset instead of open.
+    // Something had to be listening to accept it, but a firewall or
+    // load balancer that resets connections can look the same
+    pub(crate) reset_as_open: bool,
+
+    // after connecting, read whatever the service sends first so
+    // it can be used to identify the service
+    pub(crate) banners: bool,
+
+    // how long to wait on reads after connecting, separate from
+    // the connect timeout since slow banners are common
+    pub(crate) probe_timeout: Duration,
+
+    // send every connection through this SSH session instead of
+    // connecting directly
+    pub(crate) jump: Option<Arc<SshJump>>,
+
+    // open every connection with this instead of connecting directly,
+    // like a MockTransport in tests. A jump host takes precedence
+    pub(crate) transport: Option<Arc<dyn Transport>>,
+
+    // how many more times a port is tried after a retryable error
+    pub(crate) retries: u32,
+
+    // which errors are worth retrying, see DEFAULT_RETRYABLE
+    pub(crate) retryable: Vec<ErrorKind>,
+
+    // the most retries a whole scan can make across all its ports,
+    // once they're used up every port gets one try
+    pub(crate) max_retries_total: Option<usize>,
+
+    // once a few connects have answered, wait this many times their
+    // median latency on each connect instead of the whole timeout,
+    // which stays the most any connect waits
+    pub(crate) timeout_scaling: Option<f64>,
+
+    // give up on a target once this many ports in a row have timed
+    // out, since a host that went down mid-scan would otherwise wait
+    // out the whole timeout on every port left
+    pub(crate) abort_after_timeouts: Option<usize>,
+
+    // start a scan at a fraction of the concurrency and grow it to the
+    // whole thing over this long, so a target isn't hit with every
+    // connect at once and the first ports don't time out from the burst
+    pub(crate) ramp_up: Option<Duration>,
+
+    // scan every port once with this much shorter timeout and nothing
+    // else that slows a connect down, then only scan the ports that
+    // didn't refuse again with the rest of the options to confirm them
+    pub(crate) first_pass_timeout: Option<Duration>,
+
+    // start connects in batches of this many, pausing for the duration
+    // after each batch before starting the next
+    pub(crate) batch: Option<(usize, Duration)>,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions {
+            timeout: Duration::from_secs(2),
+            concurrency: 1000,
+            warmup: false,
+            source_port: None,
+            source_ips: Vec::new(),
+            next_source_ip: Arc::new(AtomicUsize::new(0)),
+            linger: None,
+            graceful_close: false,
+            reset_as_open: false,
+            banners: false,
+            probe_timeout: Duration::from_secs(2),
+            jump: None,
+            transport: None,
+            retries: 0,
+            retryable: DEFAULT_RETRYABLE.to_vec(),
+            max_retries_total: None,
+            timeout_scaling: None,
+            abort_after_timeouts: None,
+            ramp_up: None,
+            first_pass_timeout: None,
+            batch: None,
+        }
+    }
+}
+
+impl ScanOptions {
+    pub fn builder() -> ScanOptionsBuilder {
+        ScanOptionsBuilder::default()
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    // whether a port that failed with kind should be tried again
+    // rather than taken as closed
+    pub fn is_retryable(&self, kind: ErrorKind) -> bool {
+        self.retryable.contains(&kind)
+    }
+
+    // the retry budget for a whole scan, None if only the per port
+    // retries limit it
+    pub fn max_retries_total(&self) -> Option<usize> {
+        self.max_retries_total
+    }
+
+    // what the scan's connections are opened with
+    pub(crate) fn transport(&self) -> &dyn Transport {
+        match (&self.jump, &self.transport) {
+            (Some(jump), _) => jump.as_ref(),
+            (None, Some(transport)) => transport.as_ref(),
+            (None, None) => &TcpTransport,
+        }
+    }
+
+    // the options that can change what a scan finds, as text, so two
+    // scans with the same key would have found the same thing. How
+    // fast the scan goes is left out, and so is anything that changes
+    // while it runs, like which source address takes the next turn
+    pub fn result_key(&self) -> String {
+        format!(
+            "timeout={:?} source_port={:?} source_ips={:?} reset_as_open={} banners={} probe_timeout={:?} jump={:?} transport={} retries={} retryable={:?} max_retries_total={:?} timeout_scaling={:?} abort_after_timeouts={:?} first_pass_timeout={:?}",
+            self.timeout,
+            self.source_port,
+            self.source_ips,
+            self.reset_as_open,
+            self.banners,
+            self.probe_timeout,
+            self.jump,
+            self.transport.is_some(),
+            self.retries,
+            self.retryable,
+            self.max_retries_total,
+            self.timeout_scaling,
+            self.abort_after_timeouts,
+            self.first_pass_timeout,
+        )
+    }
+
+    // the local address the next connection binds to, taking the
+    // source addresses in turn, None to let the system pick
+    pub(crate) fn next_source_ip(&self) -> Option<IpAddr> {
+        if self.source_ips.is_empty() {
+            return None;
+        }
+
+        let turn: usize = self.next_source_ip.fetch_add(1, Ordering::Relaxed);
+        Some(self.source_ips[turn % self.source_ips.len()])
+    }
+
+    // the concurrency that was asked for, before anything capped it
+    pub fn requested_concurrency(&self) -> usize {
+        self.concurrency
+    }
+
+    // how many ports will really be scanned at once, binding a
+    // source port always brings this down to one and a jump host
+    // caps it
+    pub fn concurrency(&self) -> usize {
+        if self.source_port.is_some() {
+            1
+        } else if self.jump.is_some() {
+            self.concurrency.min(SSH_JUMP_CONCURRENCY)
+        } else {
+            self.concurrency
+        }
+    }
+}
+
+// builds up a ScanOptions one option at a time, anything that
+// isn't set keeps its default
+#[derive(Debug, Clone, Default)]
+pub struct ScanOptionsBuilder {
+    options: ScanOptions,
+}
+
+impl ScanOptionsBuilder {
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.options.timeout = timeout;
+        self
+    }
+
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.options.concurrency = concurrency;
+        self
+    }
+
+    pub fn warmup(mut self, warmup: bool) -> Self {
+        self.options.warmup = warmup;
+        self
+    }
+
+    pub fn source_port(mut self, source_port: u16) -> Self {
+        self.options.source_port = Some(source_port);
+        self
+    }
+
+    pub fn source_ip(mut self, source_ip: IpAddr) -> Self {
+        self.options.source_ips = vec![source_ip];
+        self
+    }
+
+    // connections take the addresses in turn, an address listed more
+    // than once gets that many turns
+    pub fn source_ips(mut self, source_ips: Vec<IpAddr>) -> Self {
+        self.options.source_ips = source_ips;
+        self
+    }
+
+    pub fn linger(mut self, linger: Duration) -> Self {
+        self.options.linger = Some(linger);
+        self
+    }
+
+    pub fn graceful_close(mut self, graceful_close: bool) -> Self {
+        self.options.graceful_close = graceful_close;
+        self
+    }
+
+    pub fn reset_as_open(mut self, reset_as_open: bool) -> Self {
+        self.options.reset_as_open = reset_as_open;
+        self
+    }
+
+    pub fn banners(mut self, banners: bool) -> Self {
+        self.options.banners = banners;
+        self
+    }
+
+    pub fn probe_timeout(mut self, probe_timeout: Duration) -> Self {
+        self.options.probe_timeout = probe_timeout;
+        self
+    }
+
+    pub fn jump(mut self, jump: Arc<SshJump>) -> Self {
+        self.options.jump = Some(jump);
+        self
+    }
+
+    pub fn transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        self.options.transport = Some(transport);
+        self
+    }
+
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.options.retries = retries;
+        self
+    }
+
+    pub fn retryable(mut self, retryable: Vec<ErrorKind>) -> Self {
+        self.options.retryable = retryable;
+        self
+    }
+
+    pub fn max_retries_total(mut self, max_retries_total: usize) -> Self {
+        self.options.max_retries_total = Some(max_retries_total);
+        self
+    }
+
+    pub fn timeout_scaling(mut self, timeout_scaling: f64) -> Self {
+        self.options.timeout_scaling = Some(timeout_scaling);
+        self
+    }
+
+    pub fn abort_after_timeouts(mut self, abort_after_timeouts: usize) -> Self {
+        self.options.abort_after_timeouts = Some(abort_after_timeouts);
+        self
+    }
+
+    pub fn ramp_up(mut self, ramp_up: Duration) -> Self {
+        self.options.ramp_up = Some(ramp_up);
+        self
+    }
+
+    pub fn first_pass_timeout(mut self, first_pass_timeout: Duration) -> Self {
+        self.options.first_pass_timeout = Some(first_pass_timeout);
+        self
+    }
+
+    pub fn batch(mut self, size: usize, delay: Duration) -> Self {
+        self.options.batch = Some((size.max(1), delay));
+        self
+    }
+
+    pub fn build(self) -> ScanOptions {
+        self.options
+    }
+}
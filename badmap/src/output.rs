@@ -0,0 +1,698 @@
+// Turns a finished scan into the report that gets written to the
+// output file, in whichever format was asked for
+use badmap::{get_category, get_service_by_port, identify_service, Category, Cidr, Confidence, Fingerprint, PortResult, PortState, SweepMethod, SweepResult};
+use crate::http_check::HttpCheck;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+// the built-in report formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Text,
+    Json,
+    Csv,
+}
+
+// the orders the open ports of a report can be listed in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputSort {
+    // lowest port first
+    Port,
+
+    // by service name, with the ports nothing was recognized on last
+    Service,
+
+    // slowest to answer first, so slow services stand out
+    Latency,
+
+    // the order the scan found them in, which is the order the ports
+    // were scanned in
+    Discovery,
+}
+
+// a user supplied layout, each open port becomes one line of the
+// line template with {port}, {state}, {service}, {latency}, and
+// {banner} filled in. The header and footer can use {target}
+// and {open_count}
+pub struct Template {
+    pub header: Option<String>,
+    pub line: String,
+    pub footer: Option<String>,
+}
+
+// how one port did across repeated scans
+pub struct PortUptime {
+    pub port: u16,
+
+    // percent of the scans the port was open in
+    pub uptime: usize,
+
+    // how many times it went from open to closed or back
+    pub changes: usize,
+}
+
+// the summary of repeated scans of the same ports
+pub struct RepeatSummary {
+    pub runs: usize,
+    pub ports: Vec<PortUptime>,
+}
+
+// how many of the scanned ports ended up in each state, closed being
+// the ones that refused and filtered the ones that never answered
+pub struct StateCounts {
+    pub open: usize,
+    pub closed: usize,
+    pub filtered: usize,
+}
+
+impl StateCounts {
+    pub fn count(results: &[PortResult]) -> Self {
+        let count = |state: PortState| results.iter().filter(|result| result.state() == state).count();
+
+        StateCounts { open: count(PortState::Open) + count(PortState::OpenReset), closed: count(PortState::Closed), filtered: count(PortState::Filtered) }
+    }
+}
+
+// what the ports --firewall-probe sampled said, the ports being ones
+// nothing should be listening on so the answer comes from whatever is
+// in front of the host rather than a service
+pub struct FirewallProbe {
+    pub sampled: usize,
+    pub closed: usize,
+    pub filtered: usize,
+    pub open: usize,
+}
+
+impl FirewallProbe {
+    pub fn count(results: &[PortResult]) -> Self {
+        let counts: StateCounts = StateCounts::count(results);
+
+        FirewallProbe { sampled: results.len(), closed: counts.closed, filtered: counts.filtered, open: counts.open }
+    }
+
+    // reject when every sampled port sent a reset back, drop when
+    // none of them answered at all, unknown when they all accepted,
+    // and mixed for anything in between
+    pub fn policy(&self) -> &'static str {
+        if self.closed == self.sampled {
+            "reject"
+        } else if self.filtered == self.sampled {
+            "drop"
+        } else if self.open == self.sampled {
+            "unknown"
+        } else {
+            "mixed"
+        }
+    }
+}
+
+// like Firewall policy: drop (all 5 sampled closed ports timed out)
+pub fn format_firewall_probe(probe: &FirewallProbe) -> String {
+    match probe.policy() {
+        "reject" => format!("Firewall policy: reject (all {} sampled closed ports answered with a reset, so they're closed)", probe.sampled),
+        "drop" => format!("Firewall policy: drop (all {} sampled closed ports timed out, so they're filtered)", probe.sampled),
+        "unknown" => format!("Firewall policy: unknown (all {} sampled closed ports accepted the connection, so something like a proxy or tarpit is answering for every port)", probe.sampled),
+        _ => format!("Firewall policy: mixed ({} of {} sampled closed ports reset, {} timed out, {} open), which can be resets being rate limited or rules for some ports", probe.closed, probe.sampled, probe.filtered, probe.open),
+    }
+}
+
+// which of the baseline's open ports have closed since, out of the
+// expected number that were open in it
+pub fn format_closed_since_baseline(closed: &[u16], expected: usize) -> String {
+    if closed.is_empty() {
+        return format!("All {} ports open in the baseline are still open", expected);
+    }
+
+    let closed: Vec<String> = closed.iter().map(|port| port.to_string()).collect();
+    format!("Closed since the baseline: {} of {} ({})", closed.len(), expected, closed.join(", "))
+}
+
+// like open: 3, closed: 990, filtered: 7
+pub fn format_state_counts(counts: &StateCounts) -> String {
+    format!("open: {}, closed: {}, filtered: {}", counts.open, counts.closed, counts.filtered)
+}
+
+// everything about a finished scan that ends up in the report
+pub struct Report {
+    pub version: &'static str,
+
+    // when the report was written, if it should say so
+    pub timestamp: Option<String>,
+
+    // the command line the scan was run with, with
+    // --output-include-command
+    pub command: Option<String>,
+
+    pub target: String,
+    pub domain: Option<String>,
+
+    // whether the text report starts with the BadMap version and
+    // which target it's for, off with --no-banner
+    pub header: bool,
+
+    // lines about how the scan was done that go under the header
+    pub notes: Vec<String>,
+
+    // whether banners were grabbed, which changes how services
+    // get identified
+    pub banners: bool,
+
+    pub open_ports: Vec<PortResult>,
+
+    // every port the report is sure about, the open ones it lists and
+    // the ones that weren't open, so a port missing from open_ports can
+    // be told apart from one that never got scanned
+    pub checked_ports: Vec<u16>,
+
+    // what the banner of each open port said about its product and
+    // version, for the ports a fingerprint matched
+    pub fingerprints: HashMap<u16, Fingerprint>,
+
+    // the path --http-check asked the open web ports for, and what
+    // each of them answered
+    pub http_check: Option<String>,
+    pub http_checks: HashMap<u16, HttpCheck>,
+
+    // whether the text report lists the open ports under their
+    // service category instead of in one list
+    pub grouped: bool,
+
+    // how many ports were open, closed, and filtered, only with
+    // --report-closed-count
+    pub state_counts: Option<StateCounts>,
+
+    // whether the host rejects or drops ports that aren't open, only
+    // with --firewall-probe
+    pub firewall_probe: Option<FirewallProbe>,
+
+    // the ports the --monitor baseline had open that aren't any more
+    pub closed_since_baseline: Option<Vec<u16>>,
+
+    // how exposed the host looks based on which ports are open
+    pub exposure_score: u32,
+
+    // only there if the scan was repeated more than once
+    pub summary: Option<RepeatSummary>,
+
+    // how long scanning the target took from start to finish
+    pub duration: Duration,
+}
+
+// a host a --vertical scan found the port open on
+pub struct OpenHost {
+    pub target: String,
+    pub domain: Option<String>,
+    pub result: PortResult,
+}
+
+// one port of a --vertical scan, which is reported by port instead of
+// by host
+pub struct PortReport {
+    pub port: u16,
+    pub hosts: Vec<OpenHost>,
+
+    // how many hosts the port was scanned on
+    pub scanned: usize,
+}
+
+// the service on an open port, with a confidence when banners
+// were grabbed since that's the only time we have more to go
+// on than the port number
+pub fn get_service(result: &PortResult, banners: bool) -> Option<(&'static str, Option<Confidence>)> {
+    if banners {
+        identify_service(result.port, result.banner.as_deref()).map(|service| (service.name, Some(service.confidence)))
+    } else {
+        get_service_by_port(result.port).map(|service| (service, None))
+    }
+}
+
+// puts the open ports in the order sort asks for, ties staying in port
+// order. banners is whether services are named from the banners too
+pub fn sort_open_ports(open_ports: &mut [PortResult], sort: OutputSort, banners: bool) {
+    match sort {
+        OutputSort::Port => open_ports.sort_by_key(|result| result.port),
+        OutputSort::Service => open_ports.sort_by_cached_key(|result| {
+            let service: Option<String> = get_service(result, banners).map(|(name, _)| name.to_ascii_lowercase());
+            (service.is_none(), service, result.port)
+        }),
+        // ports without a latency, which only cached ones can be, go last
+        OutputSort::Latency => open_ports.sort_by_key(|result| (Reverse(result.latency), result.port)),
+        OutputSort::Discovery => {}
+    }
+}
+
+// latency in milliseconds, rounded to the microsecond
+pub fn get_latency_ms(result: &PortResult) -> Option<f64> {
+    result.latency.map(|latency| (latency.as_secs_f64() * 1_000_000.0).round() / 1000.0)
+}
+
+// formats an open port the same way for the terminal and the
+// text report, when banners were grabbed the service gets a
+// confidence and the banner is added on the end
+pub fn format_port_line(result: &PortResult, banners: bool) -> String {
+    let service: String = match get_service(result, banners) {
+        Some((name, Some(confidence))) => format!("{} ({})", name, confidence),
+        Some((name, None)) => name.to_string(),
+        None => "<unknown>".to_string(),
+    };
+
+    match &result.banner {
+        Some(banner) => format!("{}  {}  {}  {}", result.port, result.state(), service, banner),
+        None => format!("{}  {}  {}", result.port, result.state(), service),
+    }
+}
+
+// the status each web port gave for the --http-check path, or why it
+// didn't give one, by port
+pub fn format_http_checks(path: &str, http_checks: &HashMap<u16, HttpCheck>) -> String {
+    let mut ports: Vec<(&u16, &HttpCheck)> = http_checks.iter().collect();
+    ports.sort_by_key(|&(&port, _)| port);
+
+    let mut text = format!("HTTP check of {}\n\nPORT  STATUS\n\n", path);
+    for (port, check) in ports {
+        match check {
+            Ok(response) if response.redirects_to_https => text.push_str(&format!("{}  {} HTTP → redirects to HTTPS ({})\n", port, response.status, response.location.as_deref().unwrap_or(""))),
+            Ok(response) => match &response.location {
+                Some(location) => text.push_str(&format!("{}  {} redirects to {}\n", port, response.status, location)),
+                None => text.push_str(&format!("{}  {}\n", port, response.status)),
+            },
+            Err(err) => text.push_str(&format!("{}  failed ({})\n", port, err)),
+        }
+    }
+
+    text
+}
+
+// formats the open ports under a heading for each service category
+// that has any, in the order the categories are declared
+pub fn format_grouped_ports(open_ports: &[PortResult], banners: bool) -> String {
+    let mut groups: BTreeMap<Category, Vec<&PortResult>> = BTreeMap::new();
+    for result in open_ports {
+        let service: Option<&str> = get_service(result, banners).map(|(name, _)| name);
+        groups.entry(get_category(service)).or_default().push(result);
+    }
+
+    let sections: Vec<String> = groups
+        .iter()
+        .map(|(category, results)| {
+            let lines: String = results.iter().map(|result| format!("{}\n", format_port_line(result, banners))).collect();
+            format!("{} ({})\n\n{}", category, results.len(), lines)
+        })
+        .collect();
+
+    sections.join("\n")
+}
+
+// formats the repeated scan summary as a table, ports that changed
+// state at all get called out as flapping
+pub fn format_summary(summary: &RepeatSummary) -> String {
+    let mut text = format!("Repeated {} scans\n\nPORT  UPTIME  CHANGES\n\n", summary.runs);
+
+    for port in &summary.ports {
+        text.push_str(&format!("{}  {}%  {}", port.port, port.uptime, port.changes));
+        if port.changes > 0 {
+            text.push_str("  flapping");
+        }
+        text.push('\n');
+    }
+
+    text
+}
+
+fn render_text(report: &Report) -> String {
+    let mut text = String::new();
+
+    if let Some(timestamp) = &report.timestamp {
+        text.push_str(&format!("{}\n", timestamp));
+    }
+
+    if let Some(command) = &report.command {
+        text.push_str(&format!("Command: {} (BadMap {})\n", command, report.version));
+    }
+
+    if report.header {
+        text.push_str(&format!("Starting BadMap {}\n", report.version));
+        match &report.domain {
+            Some(domain) => text.push_str(&format!("BadMap Scan Report for {} ({})\n", domain, report.target)),
+            None => text.push_str(&format!("BadMap Scan Report for {}\n", report.target)),
+        }
+    }
+    for note in &report.notes {
+        text.push_str(&format!("{}\n", note));
+    }
+
+    text.push_str("\nPORT  STATE  SERVICE\n\n");
+    if report.grouped {
+        text.push_str(&format_grouped_ports(&report.open_ports, report.banners));
+    } else {
+        for result in &report.open_ports {
+            text.push_str(&format!("{}\n", format_port_line(result, report.banners)));
+        }
+    }
+
+    if let Some(path) = report.http_check.as_ref().filter(|_| !report.http_checks.is_empty()) {
+        text.push_str(&format!("\n{}", format_http_checks(path, &report.http_checks)));
+    }
+
+    if let Some(closed) = report.closed_since_baseline.as_ref().filter(|closed| !closed.is_empty()) {
+        let closed: Vec<String> = closed.iter().map(|port| port.to_string()).collect();
+        text.push_str(&format!("\nClosed since the baseline: {}\n", closed.join(", ")));
+    }
+
+    if let Some(counts) = &report.state_counts {
+        text.push_str(&format!("\n{}\n", format_state_counts(counts)));
+    }
+
+    if let Some(probe) = &report.firewall_probe {
+        text.push_str(&format!("\n{}\n", format_firewall_probe(probe)));
+    }
+
+    text.push_str(&format!("\nExposure score: {}\n", report.exposure_score));
+
+    if let Some(summary) = &report.summary {
+        text.push_str(&format!("\n{}", format_summary(summary)));
+    }
+
+    text
+}
+
+fn render_json(report: &Report) -> Value {
+    let open_ports: Vec<Value> = report
+        .open_ports
+        .iter()
+        .map(|result| {
+            let service = get_service(result, report.banners);
+
+            json!({
+                "port": result.port,
+                "state": result.state().to_string(),
+                "service": service.map(|(name, _)| name),
+                "confidence": service.and_then(|(_, confidence)| confidence).map(|confidence| confidence.to_string()),
+                "banner": result.banner,
+                "fingerprint": report.fingerprints.get(&result.port).map(|fingerprint| json!({
+                    "service": fingerprint.service,
+                    "product": fingerprint.product,
+                    "version": fingerprint.version,
+                })),
+                "latency_ms": get_latency_ms(result),
+                "http_check": report.http_checks.get(&result.port).map(|check| json!({
+                    "path": report.http_check,
+                    "status": check.as_ref().ok().map(|response| response.status),
+                    "location": check.as_ref().ok().and_then(|response| response.location.as_ref()),
+                    "redirects_to_https": check.as_ref().is_ok_and(|response| response.redirects_to_https),
+                    "error": check.as_ref().err(),
+                })),
+            })
+        })
+        .collect();
+
+    let summary: Option<Value> = report.summary.as_ref().map(|summary| {
+        json!({
+            "runs": summary.runs,
+            "ports": summary.ports.iter().map(|port| json!({
+                "port": port.port,
+                "uptime_percent": port.uptime,
+                "changes": port.changes,
+                "flapping": port.changes > 0,
+            })).collect::<Vec<Value>>(),
+        })
+    });
+
+    json!({
+        "version": report.version,
+        "timestamp": report.timestamp,
+        "command": report.command,
+        "target": report.target,
+        "domain": report.domain,
+        "notes": report.notes,
+        "open_ports": open_ports,
+        "state_counts": report.state_counts.as_ref().map(|counts| json!({
+            "open": counts.open,
+            "closed": counts.closed,
+            "filtered": counts.filtered,
+        })),
+        "firewall_probe": report.firewall_probe.as_ref().map(|probe| json!({
+            "sampled": probe.sampled,
+            "closed": probe.closed,
+            "filtered": probe.filtered,
+            "open": probe.open,
+            "policy": probe.policy(),
+        })),
+        "closed_since_baseline": report.closed_since_baseline,
+        "exposure_score": report.exposure_score,
+        "repeat_summary": summary,
+    })
+}
+
+// quotes a csv field if it has anything in it that would break
+// the row up
+fn escape_csv(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn render_csv(report: &Report) -> String {
+    let mut csv = String::new();
+
+    for result in &report.open_ports {
+        let service = get_service(result, report.banners);
+
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{}\n",
+            report.target,
+            result.port,
+            result.state(),
+            service.map_or("", |(name, _)| name),
+            service.and_then(|(_, confidence)| confidence).map_or(String::new(), |confidence| confidence.to_string()),
+            get_latency_ms(result).map_or(String::new(), |latency| format!("{:.3}", latency)),
+            escape_csv(result.banner.as_deref().unwrap_or("")),
+        ));
+    }
+
+    csv
+}
+
+// lets \n and \t be typed into templates on the command line
+fn unescape(template: &str) -> String {
+    template.replace("\\n", "\n").replace("\\t", "\t")
+}
+
+fn render_template(report: &Report, template: &Template) -> String {
+    let fill_report = |template: &str| -> String {
+        unescape(template)
+            .replace("{target}", &report.target)
+            .replace("{open_count}", &report.open_ports.len().to_string())
+    };
+
+    let mut text = String::new();
+
+    if let Some(header) = &template.header {
+        text.push_str(&format!("{}\n", fill_report(header)));
+    }
+
+    for result in &report.open_ports {
+        let line = unescape(&template.line)
+            .replace("{port}", &result.port.to_string())
+            .replace("{state}", &result.state().to_string())
+            .replace("{service}", get_service(result, report.banners).map_or("<unknown>", |(name, _)| name))
+            .replace("{latency}", &get_latency_ms(result).map_or(String::new(), |latency| format!("{:.1}ms", latency)))
+            .replace("{banner}", result.banner.as_deref().unwrap_or(""));
+
+        text.push_str(&format!("{}\n", line));
+    }
+
+    if let Some(footer) = &template.footer {
+        text.push_str(&format!("{}\n", fill_report(footer)));
+    }
+
+    text
+}
+
+// renders the reports for every target one after the other, with a
+// template always taking over from the format. As json a list of
+// targets is always an array, even if it only has one report in it,
+// and a single target is just its report
+pub fn render(reports: &[Report], format: Format, template: Option<&Template>, list: bool) -> String {
+    match (template, format) {
+        (Some(template), _) => reports.iter().map(|report| render_template(report, template)).collect(),
+        (None, Format::Text) => reports.iter().map(render_text).collect::<Vec<String>>().join("\n"),
+        (None, Format::Json) => {
+            let json: Value = if list || reports.len() != 1 {
+                Value::Array(reports.iter().map(render_json).collect())
+            } else {
+                render_json(&reports[0])
+            };
+
+            format!("{}\n", serde_json::to_string_pretty(&json).unwrap())
+        }
+        (None, Format::Csv) => {
+            let rows: String = reports.iter().map(render_csv).collect();
+
+            format!("target,port,state,service,confidence,latency_ms,banner\n{}", rows)
+        }
+    }
+}
+
+// the --summary-only report, just enough json for a health check to
+// see what's open. Like render a list of targets is always an array
+pub fn render_summary_only(reports: &[Report], list: bool) -> String {
+    let summaries: Vec<Value> = reports
+        .iter()
+        .map(|report| {
+            json!({
+                "target": report.target,
+                "open_count": report.open_ports.len(),
+                "open_ports": report.open_ports.iter().map(|result| result.port).collect::<Vec<u16>>(),
+                "duration_ms": report.duration.as_millis() as u64,
+            })
+        })
+        .collect();
+
+    let json: Value = if list || summaries.len() != 1 { Value::Array(summaries) } else { summaries.into_iter().next().unwrap() };
+
+    format!("{}\n", serde_json::to_string(&json).unwrap())
+}
+
+// renders a --sweep of range, which only has hosts rather than
+// ports so it gets its own layout in each format
+pub fn render_sweep(range: &Cidr, sweep: &SweepResult, format: Format, timestamp: Option<&str>) -> String {
+    let method: &str = match sweep.method {
+        SweepMethod::Icmp => "icmp",
+        SweepMethod::Tcp => "tcp",
+    };
+    let latency_ms = |latency: Duration| (latency.as_secs_f64() * 1_000_000.0).round() / 1000.0;
+
+    match format {
+        Format::Text => {
+            let mut text = String::new();
+
+            if let Some(timestamp) = timestamp {
+                text.push_str(&format!("{}\n", timestamp));
+            }
+
+            text.push_str(&format!("BadMap Sweep Report for {} ({})\n", range, method));
+            text.push_str("\nHOST  LATENCY\n\n");
+            for host in &sweep.hosts {
+                text.push_str(&format!("{}  {:.1}ms\n", host.ip, latency_ms(host.latency)));
+            }
+            text.push_str(&format!("\n{} hosts up\n", sweep.hosts.len()));
+
+            text
+        }
+        Format::Json => {
+            let json: Value = json!({
+                "timestamp": timestamp,
+                "range": range.to_string(),
+                "method": method,
+                "hosts": sweep.hosts.iter().map(|host| json!({
+                    "ip": host.ip.to_string(),
+                    "latency_ms": latency_ms(host.latency),
+                })).collect::<Vec<Value>>(),
+            });
+
+            format!("{}\n", serde_json::to_string_pretty(&json).unwrap())
+        }
+        Format::Csv => {
+            let rows: String = sweep.hosts.iter().map(|host| format!("{},{},{:.3}\n", method, host.ip, latency_ms(host.latency))).collect();
+
+            format!("method,host,latency_ms\n{}", rows)
+        }
+    }
+}
+
+// the host column of a --vertical report, with the domain in front
+// when the host came from one
+pub fn format_open_host(host: &OpenHost) -> String {
+    let name: String = match &host.domain {
+        Some(domain) => format!("{} ({})", domain, host.target),
+        None => host.target.clone(),
+    };
+
+    match &host.result.banner {
+        Some(banner) => format!("{}  {}  {}", name, host.result.state(), banner),
+        None => format!("{}  {}", name, host.result.state()),
+    }
+}
+
+// renders a --vertical scan, with a section for each port listing
+// the hosts it was open on
+pub fn render_vertical(reports: &[PortReport], format: Format, timestamp: Option<&str>) -> String {
+    // the service a port is named by is only its well known one, the
+    // banners on each host could each say something different
+    let service = |report: &PortReport| get_service_by_port(report.port);
+
+    match format {
+        Format::Text => {
+            let mut text = String::new();
+
+            if let Some(timestamp) = timestamp {
+                text.push_str(&format!("{}\n", timestamp));
+            }
+
+            let sections: Vec<String> = reports
+                .iter()
+                .map(|report| {
+                    let mut section: String = format!("BadMap Port Report for {} ({})\n", report.port, service(report).unwrap_or("<unknown>"));
+                    section.push_str("\nHOST  STATE\n\n");
+                    for host in &report.hosts {
+                        section.push_str(&format!("{}\n", format_open_host(host)));
+                    }
+                    section.push_str(&format!("\nOpen on {} of {} hosts\n", report.hosts.len(), report.scanned));
+                    section
+                })
+                .collect();
+            text.push_str(&sections.join("\n"));
+
+            text
+        }
+        Format::Json => {
+            let json: Value = json!({
+                "timestamp": timestamp,
+                "ports": reports.iter().map(|report| json!({
+                    "port": report.port,
+                    "service": service(report),
+                    "scanned_hosts": report.scanned,
+                    "open_hosts": report.hosts.iter().map(|host| json!({
+                        "target": host.target,
+                        "domain": host.domain,
+                        "state": host.result.state().to_string(),
+                        "banner": host.result.banner,
+                        "latency_ms": get_latency_ms(&host.result),
+                    })).collect::<Vec<Value>>(),
+                })).collect::<Vec<Value>>(),
+            });
+
+            format!("{}\n", serde_json::to_string_pretty(&json).unwrap())
+        }
+        Format::Csv => {
+            let mut csv: String = "port,service,target,domain,state,latency_ms,banner\n".to_string();
+
+            for report in reports {
+                for host in &report.hosts {
+                    csv.push_str(&format!(
+                        "{},{},{},{},{},{},{}\n",
+                        report.port,
+                        service(report).unwrap_or(""),
+                        host.target,
+                        host.domain.as_deref().unwrap_or(""),
+                        host.result.state(),
+                        get_latency_ms(&host.result).map_or(String::new(), |latency| format!("{:.3}", latency)),
+                        escape_csv(host.result.banner.as_deref().unwrap_or("")),
+                    ));
+                }
+            }
+
+            csv
+        }
+    }
+}
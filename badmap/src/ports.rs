@@ -0,0 +1,241 @@
+// Parsing which ports to scan from --ports, and the ports that get
+// scanned when nothing says otherwise
+use std::collections::HashSet;
+use std::fmt;
+
+// the 1000 TCP ports nmap finds open most often, in port order, which
+// is what gets scanned by default. Written as a port spec so it reads
+// the same as nmap's own list
+static TOP_PORTS: &[&str] = &[
+    "1,3-4,6-7,9,13,17,19-26,30,32-33,37,42-43,49,53,70,79-85,88-90,99-100,106,109-111,113,119,",
+    "125,135,139,143-144,146,161,163,179,199,211-212,222,254-256,259,264,280,301,306,311,340,",
+    "366,389,406-407,416-417,425,427,443-445,458,464-465,481,497,500,512-515,524,541,543-545,",
+    "548,554-555,563,587,593,616-617,625,631,636,646,648,666-668,683,687,691,700,705,711,714,",
+    "720,722,726,749,765,777,783,787,800-801,808,843,873,880,888,898,900-903,911-912,981,987,",
+    "990,992-993,995,999-1002,1007,1009-1011,1021-1100,1102,1104-1108,1110-1114,1117,1119,",
+    "1121-1124,1126,1130-1132,1137-1138,1141,1145,1147-1149,1151-1152,1154,1163-1166,1169,",
+    "1174-1175,1183,1185-1187,1192,1198-1199,1201,1213,1216-1218,1233-1234,1236,1244,1247-1248,",
+    "1259,1271-1272,1277,1287,1296,1300-1301,1309-1311,1322,1328,1334,1352,1417,1433-1434,1443,",
+    "1455,1461,1494,1500-1501,1503,1521,1524,1533,1556,1580,1583,1594,1600,1641,1658,1666,",
+    "1687-1688,1700,1717-1721,1723,1755,1761,1782-1783,1801,1805,1812,1839-1840,1862-1864,1875,",
+    "1900,1914,1935,1947,1971-1972,1974,1984,1998-2010,2013,2020-2022,2030,2033-2035,2038,",
+    "2040-2043,2045-2049,2065,2068,2099-2100,2103,2105-2107,2111,2119,2121,2126,2135,2144,",
+    "2160-2161,2170,2179,2190-2191,2196,2200,2222,2251,2260,2288,2301,2323,2366,2381-2383,",
+    "2393-2394,2399,2401,2492,2500,2522,2525,2557,2601-2602,2604-2605,2607-2608,2638,2701-2702,",
+    "2710,2717-2718,2725,2800,2809,2811,2869,2875,2909-2910,2920,2967-2968,2998,3000-3001,3003,",
+    "3005-3007,3011,3013,3017,3030-3031,3052,3071,3077,3128,3168,3211,3221,3260-3261,3268-3269,",
+    "3283,3300-3301,3306,3322-3325,3333,3351,3367,3369-3372,3389-3390,3404,3476,3493,3517,3527,",
+    "3546,3551,3580,3659,3689-3690,3703,3737,3766,3784,3800-3801,3809,3814,3826-3828,3851,3869,",
+    "3871,3878,3880,3889,3905,3914,3918,3920,3945,3971,3986,3995,3998,4000-4006,4045,4111,",
+    "4125-4126,4129,4224,4242,4279,4321,4343,4443-4446,4449,4550,4567,4662,4848,4899-4900,4998,",
+    "5000-5004,5009,5030,5033,5050-5051,5054,5060-5061,5080,5087,5100-5102,5120,5190,5200,5214,",
+    "5221-5222,5225-5226,5269,5280,5298,5357,5405,5414,5431-5432,5440,5500,5510,5544,5550,5555,",
+    "5560,5566,5631,5633,5666,5678-5679,5718,5730,5800-5802,5810-5811,5815,5822,5825,5850,5859,",
+    "5862,5877,5900-5904,5906-5907,5910-5911,5915,5922,5925,5950,5952,5959-5963,5987-5989,",
+    "5998-6007,6009,6025,6059,6100-6101,6106,6112,6123,6129,6156,6346,6389,6502,6510,6543,6547,",
+    "6565-6567,6580,6646,6666-6669,6689,6692,6699,6779,6788-6789,6792,6839,6881,6901,6969,",
+    "7000-7002,7004,7007,7019,7025,7070,7100,7103,7106,7200-7201,7402,7435,7443,7496,7512,7625,",
+    "7627,7676,7741,7777-7778,7800,7911,7920-7921,7937-7938,7999-8002,8007-8011,8021-8022,8031,",
+    "8042,8045,8080-8090,8093,8099-8100,8180-8181,8192-8194,8200,8222,8254,8290-8292,8300,8333,",
+    "8383,8400,8402,8443,8500,8600,8649,8651-8652,8654,8701,8800,8873,8888,8899,8994,9000-9003,",
+    "9009-9011,9040,9050,9071,9080-9081,9090-9091,9099-9103,9110-9111,9200,9207,9220,9290,9415,",
+    "9418,9485,9500,9502-9503,9535,9575,9593-9595,9618,9666,9876-9878,9898,9900,9917,9929,",
+    "9943-9944,9968,9998-10004,10009-10010,10012,10024-10025,10082,10180,10215,10243,10566,",
+    "10616-10617,10621,10626,10628-10629,10778,11110-11111,11967,12000,12174,12265,12345,13456,",
+    "13722,13782-13783,14000,14238,14441-14442,15000,15002-15004,15660,15742,16000-16001,16012,",
+    "16016,16018,16080,16113,16992-16993,17877,17988,18040,18101,18988,19101,19283,19315,19350,",
+    "19780,19801,19842,20000,20005,20031,20221-20222,20828,21571,22939,23502,24444,24800,",
+    "25734-25735,26214,27000,27352-27353,27355-27356,27715,28201,30000,30718,30951,31038,31337,",
+    "32768-32785,33354,33899,34571-34573,35500,38292,40193,40911,41511,42510,44176,44442-44443,",
+    "44501,45100,48080,49152-49161,49163,49165,49167,49175-49176,49400,49999-50003,50006,50300,",
+    "50389,50500,50636,50800,51103,51493,52673,52822,52848,52869,54045,54328,55055-55056,55555,",
+    "55600,56737-56738,57294,57797,58080,60020,60443,61532,61900,62078,63331,64623,64680,65000,",
+    "65129,65389",
+];
+
+// what was wrong with one comma-separated token of a port spec
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PortSpecErrorKind {
+    // nothing between two commas, or at either end
+    Empty,
+    // not a number from 1 to 65535
+    InvalidPort(String),
+    // a range like 100-50 whose start comes after its end
+    Backwards(u16, u16),
+}
+
+// a port spec that couldn't be parsed, with the token that failed,
+// counting from 1, so a long spec points right at the mistake
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortSpecError {
+    pub token: String,
+    pub position: usize,
+    pub kind: PortSpecErrorKind,
+}
+
+impl fmt::Display for PortSpecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            PortSpecErrorKind::Empty => write!(f, "empty port at token {}", self.position),
+            PortSpecErrorKind::InvalidPort(port) if self.token.contains('-') => write!(f, "invalid range '{}': '{}' is not a port from 1 to 65535 at token {}", self.token, port, self.position),
+            PortSpecErrorKind::InvalidPort(port) => write!(f, "invalid port '{}': not a port from 1 to 65535 at token {}", port, self.position),
+            PortSpecErrorKind::Backwards(first, last) => write!(f, "invalid range '{}': start {} exceeds end {} at token {}", self.token, first, last, self.position),
+        }
+    }
+}
+
+impl std::error::Error for PortSpecError {}
+
+// parses a port spec like 22,80,8000-8100 into the ports it covers,
+// in the order they were written with any repeats dropped. A spec of
+// just - is every port, the same as nmap's -p-
+pub fn parse_port_spec(spec: &str) -> Result<Vec<u16>, PortSpecError> {
+    if spec.trim() == "-" {
+        return Ok((1..=65535).collect());
+    }
+
+    let mut ports: Vec<u16> = Vec::new();
+    let mut seen: HashSet<u16> = HashSet::new();
+
+    for (index, part) in spec.split(',').enumerate() {
+        let token: &str = part.trim();
+        let error = |kind: PortSpecErrorKind| PortSpecError { token: token.to_string(), position: index + 1, kind };
+
+        if token.is_empty() {
+            return Err(error(PortSpecErrorKind::Empty));
+        }
+
+        let parse_port = |port: &str| -> Result<u16, PortSpecError> {
+            match port.trim().parse::<u16>() {
+                Ok(0) | Err(_) => Err(error(PortSpecErrorKind::InvalidPort(port.trim().to_string()))),
+                Ok(port) => Ok(port),
+            }
+        };
+
+        let (first, last): (u16, u16) = match token.split_once('-') {
+            Some((first, last)) => (parse_port(first)?, parse_port(last)?),
+            None => {
+                let port: u16 = parse_port(token)?;
+                (port, port)
+            }
+        };
+
+        if first > last {
+            return Err(error(PortSpecErrorKind::Backwards(first, last)));
+        }
+
+        ports.extend((first..=last).filter(|&port| seen.insert(port)));
+    }
+
+    Ok(ports)
+}
+
+// writes ports back out as a port spec, with runs of consecutive
+// ports as ranges, so a long list of ports stays readable
+pub fn format_port_spec(ports: &[u16]) -> String {
+    let mut runs: Vec<(u16, u16)> = Vec::new();
+    for &port in ports {
+        match runs.last_mut() {
+            Some((_, end)) if end.checked_add(1) == Some(port) => *end = port,
+            _ => runs.push((port, port)),
+        }
+    }
+
+    runs.iter().map(|&(start, end)| if start == end { start.to_string() } else { format!("{}-{}", start, end) }).collect::<Vec<String>>().join(",")
+}
+
+// moves the ports in priority to the front, in the order priority
+// lists them, with the rest keeping their order behind them. Only
+// reorders, a priority port that isn't in ports isn't added
+pub fn prioritize_ports(ports: Vec<u16>, priority: &[u16]) -> Vec<u16> {
+    let (mut first, rest): (Vec<u16>, Vec<u16>) = ports.into_iter().partition(|port| priority.contains(port));
+    first.sort_by_key(|port| priority.iter().position(|priority| priority == port));
+
+    first.into_iter().chain(rest).collect()
+}
+
+// the ports scanned when no ports, profile, or --all-ports are given
+pub fn top_ports() -> Vec<u16> {
+    parse_port_spec(&TOP_PORTS.concat()).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec_error(spec: &str) -> PortSpecError {
+        parse_port_spec(spec).unwrap_err()
+    }
+
+    #[test]
+    fn parses_ports_and_ranges_in_order() {
+        assert_eq!(parse_port_spec("443,22,80-82").unwrap(), vec![443, 22, 80, 81, 82]);
+        assert_eq!(parse_port_spec(" 22 , 21-23 , 22").unwrap(), vec![22, 21, 23]);
+        assert_eq!(parse_port_spec("65535").unwrap(), vec![65535]);
+        assert_eq!(parse_port_spec("-").unwrap().len(), 65535);
+    }
+
+    #[test]
+    fn reports_a_backwards_range() {
+        let err: PortSpecError = spec_error("22,100-50,443");
+        assert_eq!(err.token, "100-50");
+        assert_eq!(err.position, 2);
+        assert_eq!(err.kind, PortSpecErrorKind::Backwards(100, 50));
+        assert_eq!(err.to_string(), "invalid range '100-50': start 100 exceeds end 50 at token 2");
+    }
+
+    #[test]
+    fn reports_a_port_out_of_range() {
+        let err: PortSpecError = spec_error("80,65536");
+        assert_eq!(err.token, "65536");
+        assert_eq!(err.position, 2);
+        assert_eq!(err.kind, PortSpecErrorKind::InvalidPort("65536".to_string()));
+
+        let err: PortSpecError = spec_error("0");
+        assert_eq!(err.token, "0");
+        assert_eq!(err.position, 1);
+        assert_eq!(err.kind, PortSpecErrorKind::InvalidPort("0".to_string()));
+
+        let err: PortSpecError = spec_error("1,2,1000-70000");
+        assert_eq!(err.token, "1000-70000");
+        assert_eq!(err.position, 3);
+        assert_eq!(err.kind, PortSpecErrorKind::InvalidPort("70000".to_string()));
+        assert_eq!(err.to_string(), "invalid range '1000-70000': '70000' is not a port from 1 to 65535 at token 3");
+    }
+
+    #[test]
+    fn reports_an_empty_token() {
+        let err: PortSpecError = spec_error("22,,80");
+        assert_eq!(err.token, "");
+        assert_eq!(err.position, 2);
+        assert_eq!(err.kind, PortSpecErrorKind::Empty);
+
+        assert_eq!(spec_error("22,80,").position, 3);
+        assert_eq!(spec_error("").position, 1);
+        assert_eq!(spec_error(" , 22").kind, PortSpecErrorKind::Empty);
+    }
+
+    #[test]
+    fn reports_garbage() {
+        let err: PortSpecError = spec_error("22,ssh,80");
+        assert_eq!(err.token, "ssh");
+        assert_eq!(err.position, 2);
+        assert_eq!(err.kind, PortSpecErrorKind::InvalidPort("ssh".to_string()));
+        assert_eq!(err.to_string(), "invalid port 'ssh': not a port from 1 to 65535 at token 2");
+
+        let err: PortSpecError = spec_error("80-");
+        assert_eq!(err.token, "80-");
+        assert_eq!(err.kind, PortSpecErrorKind::InvalidPort(String::new()));
+
+        let err: PortSpecError = spec_error("1-2-3");
+        assert_eq!(err.token, "1-2-3");
+        assert_eq!(err.kind, PortSpecErrorKind::InvalidPort("2-3".to_string()));
+    }
+
+    #[test]
+    fn formats_runs_as_ranges() {
+        assert_eq!(format_port_spec(&[22, 80, 81, 82, 443]), "22,80-82,443");
+        assert_eq!(format_port_spec(&[65534, 65535]), "65534-65535");
+        assert_eq!(top_ports().len(), 1000);
+    }
+}
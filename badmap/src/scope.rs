@@ -0,0 +1,141 @@
+// A scope policy is a list of allowed and denied ranges that
+// targets are checked against before anything gets scanned, for
+// locked-down setups where only approved ranges may ever be scanned.
+//
+// Policies are written one rule per line:
+//
+//     # only the lab network, but never the gateway
+//     allow 10.20.0.0/16
+//     deny 10.20.0.1
+use crate::cidr::Cidr;
+
+use std::net::IpAddr;
+
+#[derive(Debug, Clone, Default)]
+pub struct ScopePolicy {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+}
+
+impl ScopePolicy {
+    // parses a policy, any line that isn't a valid rule is an
+    // error so a typo can't silently widen what's allowed
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let mut policy = ScopePolicy::default();
+
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (rule, range) = match line.split_once(char::is_whitespace) {
+                Some((rule, range)) => (rule, range.trim()),
+                None => return Err(format!("line {}: expected a rule and a range: {}", number + 1, line)),
+            };
+            let range: Cidr = range.parse().map_err(|err| format!("line {}: {}", number + 1, err))?;
+
+            match rule {
+                "allow" => policy.allow.push(range),
+                "deny" => policy.deny.push(range),
+                _ => return Err(format!("line {}: unknown rule {} (expected allow or deny)", number + 1, rule)),
+            }
+        }
+
+        Ok(policy)
+    }
+
+    // a target has to be in an allowed range (when there are any)
+    // and can't be in any denied range. An IPv4-mapped IPv6 address
+    // is checked as the IPv4 address it gets connected to
+    pub fn check(&self, ip: IpAddr) -> Result<(), String> {
+        let ip: IpAddr = ip.to_canonical();
+
+        if let Some(range) = self.deny.iter().find(|range| range.contains(ip)) {
+            return Err(format!("{} is inside the denied range {}", ip, range));
+        }
+
+        if !self.allow.is_empty() && !self.allow.iter().any(|range| range.contains(ip)) {
+            return Err(format!("{} is outside every allowed range", ip));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(contents: &str) -> ScopePolicy {
+        ScopePolicy::parse(contents).unwrap()
+    }
+
+    fn allowed(policy: &ScopePolicy, ip: &str) -> bool {
+        policy.check(ip.parse().unwrap()).is_ok()
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let policy: ScopePolicy = parse("# only the lab network, but never the gateway\nallow 10.20.0.0/16\ndeny 10.20.0.1\n");
+        assert!(allowed(&policy, "10.20.5.5"));
+        assert!(!allowed(&policy, "10.20.0.1"));
+        assert!(!allowed(&policy, "10.21.0.1"));
+        assert_eq!(policy.check("10.20.0.1".parse().unwrap()).unwrap_err(), "10.20.0.1 is inside the denied range 10.20.0.1/32");
+
+        // the order of the rules doesn't matter
+        let policy: ScopePolicy = parse("deny 10.20.0.0/24\nallow 10.20.0.0/24\n");
+        assert!(!allowed(&policy, "10.20.0.7"));
+    }
+
+    #[test]
+    fn no_allow_rules_allows_everything_not_denied() {
+        let policy: ScopePolicy = parse("");
+        assert!(allowed(&policy, "8.8.8.8"));
+        assert!(allowed(&policy, "2001:db8::1"));
+
+        let policy: ScopePolicy = parse("deny 192.168.0.0/16\n");
+        assert!(allowed(&policy, "10.0.0.1"));
+        assert!(!allowed(&policy, "192.168.1.1"));
+    }
+
+    #[test]
+    fn allow_rules_leave_out_everything_else() {
+        let policy: ScopePolicy = parse("allow 10.0.0.0/8\nallow 2001:db8::/32\n");
+        assert!(allowed(&policy, "10.1.2.3"));
+        assert!(allowed(&policy, "2001:db8:1::1"));
+        assert!(!allowed(&policy, "11.0.0.1"));
+        assert_eq!(policy.check("11.0.0.1".parse().unwrap()).unwrap_err(), "11.0.0.1 is outside every allowed range");
+    }
+
+    #[test]
+    fn ipv4_rules_dont_cover_ipv6_targets() {
+        let policy: ScopePolicy = parse("allow 0.0.0.0/0\n");
+        assert!(allowed(&policy, "203.0.113.9"));
+        assert!(!allowed(&policy, "2001:db8::1"));
+
+        let policy: ScopePolicy = parse("deny 0.0.0.0/0\n");
+        assert!(!allowed(&policy, "203.0.113.9"));
+        assert!(allowed(&policy, "2001:db8::1"));
+    }
+
+    #[test]
+    fn ipv4_mapped_targets_are_checked_as_ipv4() {
+        let policy: ScopePolicy = parse("allow 10.20.0.0/16\ndeny 10.20.0.1\n");
+        assert!(!allowed(&policy, "::ffff:10.20.0.1"));
+        assert!(allowed(&policy, "::ffff:10.20.0.2"));
+        assert!(!allowed(&policy, "::ffff:10.21.0.1"));
+    }
+
+    #[test]
+    fn rejects_malformed_rules_with_their_line() {
+        let err = |contents: &str| ScopePolicy::parse(contents).unwrap_err();
+
+        assert_eq!(err("allow 10.0.0.0/8\nallow\n"), "line 2: expected a rule and a range: allow");
+        assert_eq!(err("\n# comment\npermit 10.0.0.0/8\n"), "line 3: unknown rule permit (expected allow or deny)");
+        assert_eq!(err("deny 10.0.0\n"), "line 1: invalid address in range: 10.0.0");
+        assert_eq!(err("deny 10.0.0.0/33\n"), "line 1: prefix /33 is too long for 10.0.0.0");
+        assert_eq!(err("allow 10.0.0.0/abc\n"), "line 1: invalid prefix in range: 10.0.0.0/abc");
+        assert_eq!(err("allow 10.0.0.0/8 10.1.0.0/16\n"), "line 1: invalid prefix in range: 10.0.0.0/8 10.1.0.0/16");
+    }
+}
@@ -0,0 +1,210 @@
+// Everything BadMap knows about naming services, first by the
+// well known port number and then by the banners services send
+use std::collections::HashMap;
+use std::fmt;
+
+// holds mappings from well_known_ports to it's corresponding
+// service, sorted by port
+pub static SERVICES: &[(u16, &str)] = &[
+    (21, "FTP"),
+    (22, "SSH"),
+    (23, "Telnet"),
+    (25, "SMTP"),
+    (80, "HTTP"),
+    (110, "POP3"),
+    (143, "IMAP"),
+    (443, "HTTPS"),
+    (1433, "MSSQL"),
+    (3306, "MySQL"),
+    (3389, "RDP"),
+    (5432, "PostgreSQL"),
+    (5900, "VNC"),
+    (6379, "Redis"),
+    (27017, "MongoDB"),
+];
+
+// looks up the service for a port in the table above
+pub fn get_service_by_port(port: u16) -> Option<&'static str> {
+    SERVICES.iter().find(|&&(service_port, _)| service_port == port).map(|&(_, service)| service)
+}
+
+// the transport a port number is for, the same port can be a
+// different service over TCP and UDP
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+// every port and service in the table above, in port order
+pub fn services() -> impl Iterator<Item = (u16, &'static str)> {
+    SERVICES.iter().copied()
+}
+
+// the well known service for port over protocol. BadMap only scans
+// TCP so the table only has TCP services, UDP ports never have one
+pub fn service_for_port(port: u16, protocol: Protocol) -> Option<&'static str> {
+    match protocol {
+        Protocol::Tcp => get_service_by_port(port),
+        Protocol::Udp => None,
+    }
+}
+
+// the broad kinds of service, for grouping a long list of open
+// ports into something easier to read
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Category {
+    Web,
+    Database,
+    RemoteAccess,
+    Mail,
+    Other,
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Category::Web => write!(f, "web"),
+            Category::Database => write!(f, "database"),
+            Category::RemoteAccess => write!(f, "remote-access"),
+            Category::Mail => write!(f, "mail"),
+            Category::Other => write!(f, "other"),
+        }
+    }
+}
+
+// which category each service in the table above belongs to,
+// anything not listed here is Category::Other
+pub static CATEGORIES: &[(&str, Category)] = &[
+    ("HTTP", Category::Web),
+    ("HTTPS", Category::Web),
+    ("MSSQL", Category::Database),
+    ("MySQL", Category::Database),
+    ("PostgreSQL", Category::Database),
+    ("Redis", Category::Database),
+    ("MongoDB", Category::Database),
+    ("SSH", Category::RemoteAccess),
+    ("Telnet", Category::RemoteAccess),
+    ("RDP", Category::RemoteAccess),
+    ("VNC", Category::RemoteAccess),
+    ("SMTP", Category::Mail),
+    ("POP3", Category::Mail),
+    ("IMAP", Category::Mail),
+];
+
+// looks up the category of a service, where None is a service we
+// couldn't name
+pub fn get_category(service: Option<&str>) -> Category {
+    service
+        .and_then(|service| CATEGORIES.iter().find(|&&(name, _)| name == service))
+        .map_or(Category::Other, |&(_, category)| category)
+}
+
+// recognizes the services that send a banner as soon as you
+// connect, going off how that banner starts
+fn get_service_by_banner(banner: &str) -> Option<&'static str> {
+    if banner.starts_with("SSH-") {
+        Some("SSH")
+    } else if banner.starts_with("HTTP/") {
+        Some("HTTP")
+    } else if banner.starts_with("+OK") {
+        Some("POP3")
+    } else if banner.starts_with("* OK") {
+        Some("IMAP")
+    } else if banner.starts_with("220") && banner.contains("FTP") {
+        Some("FTP")
+    } else if banner.starts_with("220") && banner.contains("SMTP") {
+        Some("SMTP")
+    } else {
+        None
+    }
+}
+
+// services that talk first, so if one of these is on its well
+// known port but we didn't get a banner that's suspicious
+fn speaks_first(service: &str) -> bool {
+    matches!(service, "SSH" | "FTP" | "SMTP" | "POP3" | "IMAP")
+}
+
+// how sure we are about a service identification
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Confidence {
+    Low,
+    Medium,
+    High,
+}
+
+impl fmt::Display for Confidence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Confidence::Low => write!(f, "low"),
+            Confidence::Medium => write!(f, "medium"),
+            Confidence::High => write!(f, "high"),
+        }
+    }
+}
+
+// a service name along with how sure we are about it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServiceMatch {
+    pub name: &'static str,
+    pub confidence: Confidence,
+}
+
+// combines the port number and the banner from a scan with banners
+// turned on into a single identification, where a None banner means
+// the service didn't send anything. A recognized banner wins no
+// matter the port, a port number on its own is only medium
+// confidence, and it drops to low when the banner doesn't back it up
+pub fn identify_service(port: u16, banner: Option<&str>) -> Option<ServiceMatch> {
+    let by_banner: Option<&'static str> = banner.and_then(get_service_by_banner);
+    let by_port: Option<&'static str> = get_service_by_port(port);
+
+    match (by_banner, by_port, banner) {
+        (Some(name), _, _) => Some(ServiceMatch { name, confidence: Confidence::High }),
+        // something answered, but not the way this service would
+        (None, Some(name), Some(_)) => Some(ServiceMatch { name, confidence: Confidence::Low }),
+        (None, Some(name), None) if speaks_first(name) => Some(ServiceMatch { name, confidence: Confidence::Low }),
+        (None, Some(name), None) => Some(ServiceMatch { name, confidence: Confidence::Medium }),
+        (None, None, _) => None,
+    }
+}
+
+// how much an open port adds to a host's exposure score, the ports
+// that get attacked the most weigh the most. Anything not listed
+// here counts for DEFAULT_EXPOSURE_WEIGHT
+pub static EXPOSURE_WEIGHTS: &[(u16, u32)] = &[
+    (21, 5),
+    (22, 2),
+    (23, 10),
+    (80, 1),
+    (135, 6),
+    (139, 6),
+    (443, 1),
+    (445, 10),
+    (1433, 6),
+    (3306, 6),
+    (3389, 8),
+    (5432, 6),
+    (5900, 8),
+    (6379, 6),
+    (27017, 6),
+];
+
+pub const DEFAULT_EXPOSURE_WEIGHT: u32 = 1;
+
+// adds up the weights of the open ports into a rough score of how
+// exposed a host is, overrides replace the built-in weight for
+// any port they have
+pub fn exposure_score(open_ports: &[u16], overrides: &HashMap<u16, u32>) -> u32 {
+    open_ports
+        .iter()
+        .map(|port| match overrides.get(port) {
+            Some(&weight) => weight,
+            None => EXPOSURE_WEIGHTS
+                .iter()
+                .find(|&&(weight_port, _)| weight_port == *port)
+                .map_or(DEFAULT_EXPOSURE_WEIGHT, |&(_, weight)| weight),
+        })
+        .sum()
+}
@@ -0,0 +1,43 @@
+// Integrity checks for saved reports with --sign, a SHA-256 sidecar
+// in the same layout sha256sum writes so `sha256sum -c` can verify
+// it, and optionally an ed25519 signature from --sign-key
+use ed25519_dalek::pkcs8::DecodePrivateKey;
+use ed25519_dalek::{Signer, SigningKey};
+use sha2::{Digest, Sha256};
+
+use std::path::Path;
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// the SHA-256 of contents as hex
+pub fn sha256_hex(contents: &[u8]) -> String {
+    to_hex(&Sha256::digest(contents))
+}
+
+// loads an ed25519 private key in PKCS#8 PEM, like the ones
+// `openssl genpkey -algorithm ed25519` makes
+pub fn load_signing_key(path: &str) -> Result<SigningKey, String> {
+    let pem: String = std::fs::read_to_string(path).map_err(|err| format!("failed to read {}: {}", path, err))?;
+
+    SigningKey::from_pkcs8_pem(&pem).map_err(|err| format!("{} isn't an ed25519 PKCS#8 key: {}", path, err))
+}
+
+// reads back the saved report at path and writes path.sha256 next to
+// it, and path.sig with the hex ed25519 signature if there's a key.
+// The file is read back rather than hashing what was written so
+// compressed reports get the hash of the .gz itself
+pub fn sign_output_file(path: &str, key: Option<&SigningKey>) -> Result<(), String> {
+    let contents: Vec<u8> = std::fs::read(path).map_err(|err| format!("failed to read {} back: {}", path, err))?;
+    let name = Path::new(path).file_name().map_or(path.into(), |name| name.to_string_lossy());
+
+    std::fs::write(format!("{}.sha256", path), format!("{}  {}\n", sha256_hex(&contents), name)).map_err(|err| err.to_string())?;
+
+    if let Some(key) = key {
+        let signature = key.sign(&contents);
+        std::fs::write(format!("{}.sig", path), format!("{}\n", to_hex(&signature.to_bytes()))).map_err(|err| err.to_string())?;
+    }
+
+    Ok(())
+}
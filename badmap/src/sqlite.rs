@@ -0,0 +1,58 @@
+// Records every scan in a SQLite database for --sqlite, so the
+// history of what was open can be queried across many runs
+use crate::output::{get_latency_ms, get_service, Report};
+
+use rusqlite::{params, Connection};
+
+// created the first time a database is used, later runs just add
+// rows to the same tables
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS scans (
+    id INTEGER PRIMARY KEY,
+    timestamp TEXT NOT NULL,
+    target TEXT NOT NULL,
+    domain TEXT,
+    options TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS results (
+    scan_id INTEGER NOT NULL REFERENCES scans(id),
+    port INTEGER NOT NULL,
+    state TEXT NOT NULL,
+    service TEXT,
+    latency_ms REAL,
+    banner TEXT
+);
+";
+
+// adds a row to scans for each report and a row to results for each
+// of its open ports, all in one transaction so a failed write doesn't
+// leave half a scan behind. options is whatever describes how the
+// scan was run, the command line for the CLI
+pub fn write_sqlite(path: &str, reports: &[Report], timestamp: &str, options: &str) -> Result<(), String> {
+    let mut connection = Connection::open(path).map_err(|err| err.to_string())?;
+    connection.execute_batch(SCHEMA).map_err(|err| err.to_string())?;
+
+    let transaction = connection.transaction().map_err(|err| err.to_string())?;
+
+    for report in reports {
+        transaction
+            .execute(
+                "INSERT INTO scans (timestamp, target, domain, options) VALUES (?1, ?2, ?3, ?4)",
+                params![timestamp, report.target, report.domain, options],
+            )
+            .map_err(|err| err.to_string())?;
+        let scan_id: i64 = transaction.last_insert_rowid();
+
+        for result in &report.open_ports {
+            transaction
+                .execute(
+                    "INSERT INTO results (scan_id, port, state, service, latency_ms, banner) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![scan_id, result.port, result.state().to_string(), get_service(result, report.banners).map(|(name, _)| name), get_latency_ms(result), result.banner],
+                )
+                .map_err(|err| err.to_string())?;
+        }
+    }
+
+    transaction.commit().map_err(|err| err.to_string())
+}
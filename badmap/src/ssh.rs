@@ -0,0 +1,114 @@
+// Scanning through an SSH jump host, every scan connection becomes a
+// direct-tcpip channel on one SSH session and whether the bastion
+// could open it tells us whether the port is open
+use crate::transport::{Connection, Transport};
+use crate::ScanOptions;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use russh::client::{self, Handle, Handler, Msg};
+use russh::keys::{check_known_hosts, load_secret_key, PrivateKeyWithHashAlg, PublicKeyOrCertificate};
+use russh::{Channel, ChannelOpenFailure};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+
+// only accepts the jump host if its key is already in known_hosts,
+// the same as ssh would with StrictHostKeyChecking
+struct KnownHosts {
+    host: String,
+    port: u16,
+}
+
+impl Handler for KnownHosts {
+    type Error = russh::Error;
+
+    async fn check_server_key(&mut self, server_key: &PublicKeyOrCertificate) -> Result<bool, Self::Error> {
+        match server_key {
+            PublicKeyOrCertificate::PublicKey { key, .. } => Ok(check_known_hosts(&self.host, self.port, key).unwrap_or(false)),
+            PublicKeyOrCertificate::Certificate(_) => Ok(false),
+        }
+    }
+}
+
+// an authenticated SSH session to the jump host
+pub struct SshJump {
+    destination: String,
+    handle: Handle<KnownHosts>,
+}
+
+impl fmt::Debug for SshJump {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SshJump").field("destination", &self.destination).finish()
+    }
+}
+
+impl SshJump {
+    // Connects to host as user with the private key at key_path. The
+    // host key has to already be in ~/.ssh/known_hosts
+    pub async fn connect(user: &str, host: &str, port: u16, key_path: &Path) -> Result<SshJump, String> {
+        let stream: TcpStream = TcpStream::connect((host, port)).await.map_err(|err| err.to_string())?;
+        stream.set_nodelay(true).map_err(|err| err.to_string())?;
+
+        SshJump::connect_stream(stream, user, host, port, key_path).await
+    }
+
+    // Same as connect, but the SSH session runs over stream, a
+    // connection to the jump host that's already been opened, like a
+    // tunnel handed over by whatever is embedding BadMap. The host and
+    // port are the ones known_hosts has the jump host's key under
+    pub async fn connect_stream<S>(stream: S, user: &str, host: &str, port: u16, key_path: &Path) -> Result<SshJump, String>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        // IPv6 hosts get brackets so the port can't be read as part
+        // of the address
+        let destination = if host.contains(':') { format!("{}@[{}]:{}", user, host, port) } else { format!("{}@{}:{}", user, host, port) };
+
+        let key = load_secret_key(key_path, None).map_err(|err| format!("failed to load {}: {}", key_path.display(), err))?;
+
+        let handler = KnownHosts { host: host.to_string(), port };
+        let mut handle: Handle<KnownHosts> = match client::connect_stream(Arc::new(client::Config::default()), stream, handler).await {
+            Ok(handle) => handle,
+            Err(russh::Error::UnknownKey) => return Err(format!("the host key for {} isn't in known_hosts", host)),
+            Err(err) => return Err(err.to_string()),
+        };
+
+        let hash_alg = handle.best_supported_rsa_hash().await.map_err(|err| err.to_string())?.flatten();
+        let auth = handle
+            .authenticate_publickey(user, PrivateKeyWithHashAlg::new(Arc::new(key), hash_alg))
+            .await
+            .map_err(|err| err.to_string())?;
+
+        if !auth.success() {
+            return Err(format!("{} didn't accept the key {}", destination, key_path.display()));
+        }
+
+        Ok(SshJump { destination, handle })
+    }
+
+    // asks the jump host to connect to address for us, the reasons a
+    // bastion gives for refusing map onto the errors a direct connect
+    // would have given
+    pub(crate) async fn open(&self, address: SocketAddr) -> io::Result<Channel<Msg>> {
+        match self.handle.channel_open_direct_tcpip(address.ip().to_string(), address.port() as u32, "127.0.0.1", 0).await {
+            Ok(channel) => Ok(channel),
+            Err(russh::Error::ChannelOpenFailure(ChannelOpenFailure::ConnectFailed)) => Err(ErrorKind::ConnectionRefused.into()),
+            Err(russh::Error::ChannelOpenFailure(ChannelOpenFailure::AdministrativelyProhibited)) => Err(ErrorKind::PermissionDenied.into()),
+            Err(err) => Err(io::Error::other(err.to_string())),
+        }
+    }
+}
+
+// every connection is a channel opened by the jump host, with the
+// banner read back through it
+impl Transport for SshJump {
+    fn connect<'a>(&'a self, address: SocketAddr, _options: &'a ScanOptions) -> BoxFuture<'a, io::Result<Box<dyn Connection>>> {
+        async move { Ok(Box::new(self.open(address).await?.into_stream()) as Box<dyn Connection>) }.boxed()
+    }
+}
@@ -0,0 +1,102 @@
+// Finding which hosts in a range are alive without scanning any of
+// their ports, using ICMP echo when we're allowed to send it and
+// falling back to TCP connects when we aren't
+use crate::{connect, CancellationToken, ScanOptions};
+
+use futures::{future, stream, FutureExt, StreamExt};
+use surge_ping::{Client, Config, PingIdentifier, PingSequence};
+
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// the ports tried on each host by the TCP fallback, a refused
+// connection counts too since something had to refuse it
+pub const TCP_LIVENESS_PORTS: &[u16] = &[80, 443, 22];
+
+// what the sweep used to check each host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SweepMethod {
+    Icmp,
+    Tcp,
+}
+
+// a host that answered, with how long it took
+#[derive(Debug, Clone)]
+pub struct LiveHost {
+    pub ip: IpAddr,
+    pub latency: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct SweepResult {
+    pub method: SweepMethod,
+
+    // only the hosts that answered, in the order they were given
+    pub hosts: Vec<LiveHost>,
+}
+
+// sends one echo request to host, ICMP has no ports so the
+// identifier is what keeps the replies apart
+async fn ping_host(client: &Client, host: Ipv4Addr, identifier: u16, timeout: Duration) -> Option<Duration> {
+    let mut pinger = client.pinger(IpAddr::V4(host), PingIdentifier(identifier)).await;
+    pinger.timeout(timeout);
+
+    pinger.ping(PingSequence(0), &[0; 8]).await.ok().map(|(_, latency)| latency)
+}
+
+// tries each of the liveness ports on host until one of them answers
+async fn connect_host(host: Ipv4Addr, options: &ScanOptions) -> Option<Duration> {
+    for port in TCP_LIVENESS_PORTS {
+        let address: SocketAddr = (host, *port).into();
+        let start = Instant::now();
+
+        match tokio::time::timeout(options.timeout, connect(address, options)).await {
+            Ok(Ok(_)) => return Some(start.elapsed()),
+            Ok(Err(err)) if err.kind() == ErrorKind::ConnectionRefused => return Some(start.elapsed()),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+// Checks every host for signs of life, up to options.concurrency at a
+// time and waiting options.timeout for each answer. ICMP is used if
+// we're allowed to open an ICMP socket, which needs either root or
+// an unprivileged ping group, otherwise every host gets TCP connects
+// to the liveness ports instead. Cancelling works the same way as
+// for scan, the hosts already being checked are waited on
+pub async fn sweep(hosts: Vec<Ipv4Addr>, options: &ScanOptions, cancel: CancellationToken) -> SweepResult {
+    let client: Option<Client> = Client::new(&Config::default()).ok();
+    let method: SweepMethod = if client.is_some() { SweepMethod::Icmp } else { SweepMethod::Tcp };
+
+    let options = Arc::new(options.clone());
+    let mut checks = stream::iter(hosts.into_iter().enumerate())
+        .take_while(|_| future::ready(!cancel.is_cancelled()))
+        .map(|(index, host)| {
+            let options = Arc::clone(&options);
+            let client = client.clone();
+
+            tokio::spawn(async move {
+                match &client {
+                    Some(client) => ping_host(client, host, index as u16, options.timeout).await,
+                    None => connect_host(host, &options).await,
+                }
+            })
+            .map(move |latency| (host, latency))
+        })
+        .buffered(options.concurrency);
+
+    let mut live: Vec<LiveHost> = Vec::new();
+    while let Some((host, latency)) = checks.next().await {
+        match latency {
+            Ok(Some(latency)) => live.push(LiveHost { ip: IpAddr::V4(host), latency }),
+            Ok(None) => {}
+            Err(err) => eprintln!("Checking host {} failed: {}", host, err),
+        }
+    }
+
+    SweepResult { method, hosts: live }
+}
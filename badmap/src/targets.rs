@@ -0,0 +1,363 @@
+// Working out which hosts to scan, from --ip, --domain, or a
+// --targets-file, and resolving any hostnames along the way
+use badmap::target_address;
+use dns_lookup::{getaddrinfo, AddrInfoHints, LookupErrorKind, SockType};
+use futures::{stream, StreamExt};
+use hickory_resolver::proto::rr::rdata::SRV;
+use hickory_resolver::proto::rr::RData;
+use hickory_resolver::TokioResolver;
+use tokio::task;
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+
+// a host to scan, the address we connect to along with the
+// domain it came from if it had to be looked up
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub ip: String,
+    pub domain: Option<String>,
+
+    // the domain's other IPv4 addresses, in the order they resolved,
+    // to fall back on if ip turns out to be unreachable
+    pub fallbacks: Vec<String>,
+
+    // the only port to scan, for a target from an SRV record that
+    // says which port its service is on
+    pub port: Option<u16>,
+}
+
+impl Target {
+    pub fn from_ip(ip: IpAddr, domain: Option<String>) -> Self {
+        Target { ip: ip.to_string(), domain, fallbacks: Vec::new(), port: None }
+    }
+}
+
+// the most lookups a target list has going at once, past this a
+// slow resolver just queues them up anyway
+const RESOLVE_CONCURRENCY: usize = 32;
+
+// resolves hostnames to the address we'll scan, remembering each
+// answer so a hostname that shows up more than once in a target
+// list only gets looked up the first time
+pub struct Resolver {
+    // None when caching is turned off, for round-robin DNS where
+    // each lookup can give a different host
+    cache: Option<HashMap<String, Vec<IpAddr>>>,
+}
+
+// looks up every IPv4 address for domain, in the order the resolver
+// gave them, on a blocking thread since getaddrinfo blocks
+async fn lookup_ipv4(domain: String) -> Result<Vec<IpAddr>, String> {
+    let domain_copy = domain.clone();
+    let ips: Vec<IpAddr> = match task::spawn_blocking(move || lookup_host(&domain_copy)).await.unwrap() {
+        Ok(ips) => ips.into_iter().map(|ip| ip.to_canonical()).filter(|ip| ip.is_ipv4()).collect(),
+        Err(kind) if resolver_unavailable(kind) => return Err(format!("Failed to resolve domain: {} (DNS doesn't seem to be working on this system, check /etc/resolv.conf or pass the address with --ip)", domain)),
+        Err(_) => return Err(format!("Failed to resolve domain: {}", domain)),
+    };
+
+    if ips.is_empty() {
+        return Err(format!("Failed to resolve domain into IPv4 address: {}", domain));
+    }
+
+    Ok(ips)
+}
+
+// the target for domain, scanning its first IPv4 address and keeping
+// the rest to fall back on
+fn domain_target(domain: &str, ips: &[IpAddr]) -> Target {
+    let mut ips: Vec<String> = ips.iter().map(|ip| ip.to_string()).collect();
+    ips.dedup();
+    let ip: String = ips.remove(0);
+
+    Target { ip, domain: Some(domain.to_string()), fallbacks: ips, port: None }
+}
+
+impl Resolver {
+    pub fn new(use_cache: bool) -> Self {
+        Resolver {
+            cache: use_cache.then(HashMap::new),
+        }
+    }
+
+    // looks up every IPv4 address for domain, in the order the
+    // resolver gave them
+    pub async fn resolve(&mut self, domain: &str) -> Result<Vec<IpAddr>, String> {
+        self.resolve_all(&[domain]).await.remove(0)
+    }
+
+    // looks up every domain at once, up to RESOLVE_CONCURRENCY at a
+    // time, giving back the answers in the same order. With the cache
+    // on, a domain listed more than once is only looked up once
+    pub async fn resolve_all(&mut self, domains: &[&str]) -> Vec<Result<Vec<IpAddr>, String>> {
+        let mut pending: Vec<&str> = domains.to_vec();
+        if let Some(cache) = &self.cache {
+            pending.retain(|domain| !cache.contains_key(*domain));
+            pending.sort_unstable();
+            pending.dedup();
+        }
+
+        let answers: Vec<Result<Vec<IpAddr>, String>> = stream::iter(pending.iter().map(|domain| lookup_ipv4(domain.to_string())))
+            .buffered(RESOLVE_CONCURRENCY)
+            .collect()
+            .await;
+
+        let Some(cache) = self.cache.as_mut() else {
+            return answers;
+        };
+
+        // failures aren't cached, so they're kept to one side
+        let mut failures: HashMap<&str, String> = HashMap::new();
+        for (domain, answer) in pending.iter().zip(answers) {
+            match answer {
+                Ok(ips) => {
+                    cache.insert(domain.to_string(), ips);
+                }
+                Err(err) => {
+                    failures.insert(domain, err);
+                }
+            }
+        }
+
+        domains
+            .iter()
+            .map(|domain| match cache.get(*domain) {
+                Some(ips) => Ok(ips.clone()),
+                None => Err(failures[domain].clone()),
+            })
+            .collect()
+    }
+
+    // the target for domain, scanning its first IPv4 address and
+    // keeping the rest to fall back on
+    pub async fn resolve_target(&mut self, domain: &str) -> Result<Target, String> {
+        Ok(domain_target(domain, &self.resolve(domain).await?))
+    }
+}
+
+// looks up the SRV records of name, like _sip._tcp.example.com, giving
+// back a target for each host they point at to be scanned on the port
+// its record gives, in the order a client would try them: lowest
+// priority first, then heaviest weight. getaddrinfo can only look up
+// addresses, so the records come from a DNS client of our own using
+// the system's nameservers, and the hosts they point at are looked up
+// the same as any other domain
+pub async fn resolve_srv(name: &str, resolver: &mut Resolver) -> Result<Vec<Target>, String> {
+    let client: TokioResolver = TokioResolver::builder_tokio()
+        .and_then(|builder| builder.build())
+        .map_err(|err| format!("Failed to set up DNS for looking up SRV records: {}", err))?;
+    let lookup = client.srv_lookup(name).await.map_err(|err| format!("Failed to look up SRV records for {}: {}", name, err))?;
+
+    // a target of . means the service is deliberately not offered
+    let mut records: Vec<&SRV> = lookup
+        .answers()
+        .iter()
+        .filter_map(|record| match &record.data {
+            RData::SRV(srv) if !srv.target.is_root() => Some(srv),
+            _ => None,
+        })
+        .collect();
+    if records.is_empty() {
+        return Err(format!("No SRV records for {} point at a host", name));
+    }
+    records.sort_by_key(|srv| (srv.priority, Reverse(srv.weight)));
+
+    let hosts: Vec<String> = records.iter().map(|srv| srv.target.to_utf8().trim_end_matches('.').to_string()).collect();
+    let answers = resolver.resolve_all(&hosts.iter().map(String::as_str).collect::<Vec<&str>>()).await;
+
+    let mut targets: Vec<Target> = Vec::new();
+    for ((srv, host), answer) in records.iter().zip(&hosts).zip(answers) {
+        match answer {
+            Ok(ips) => targets.push(Target { port: Some(srv.port), ..domain_target(host, &ips) }),
+            Err(err) => eprintln!("Skipping target: {}", err),
+        }
+    }
+
+    Ok(targets)
+}
+
+// looks up host's addresses, keeping the kind of error on a failure so
+// a name that doesn't exist can be told from DNS not working at all
+fn lookup_host(host: &str) -> Result<Vec<IpAddr>, LookupErrorKind> {
+    let hints = AddrInfoHints { socktype: SockType::Stream.into(), ..AddrInfoHints::default() };
+
+    match getaddrinfo(Some(host), None, Some(hints)) {
+        Ok(addresses) => Ok(addresses.filter_map(Result::ok).map(|address| address.sockaddr.ip()).collect()),
+        Err(err) => Err(err.kind()),
+    }
+}
+
+// whether a failed lookup means there's no working resolver, rather
+// than the name just not existing. Either no nameserver is configured
+// or the lookup couldn't reach one
+fn resolver_unavailable(kind: LookupErrorKind) -> bool {
+    let configured: bool = match std::fs::read_to_string("/etc/resolv.conf") {
+        Ok(contents) => contents.lines().any(|line| line.trim_start().starts_with("nameserver")),
+        // other systems keep their resolver setup somewhere else
+        Err(_) => !cfg!(target_os = "linux"),
+    };
+
+    !configured || matches!(kind, LookupErrorKind::Again | LookupErrorKind::Fail)
+}
+
+// checks that ip is an address we know how to scan. An IPv4-mapped
+// IPv6 address like ::ffff:192.0.2.1, which dual-stack systems hand
+// out for IPv4 hosts, is the IPv4 address it wraps
+pub fn parse_ip(ip: &str) -> Result<IpAddr, String> {
+    match ip.parse::<IpAddr>().map(|parsed| parsed.to_canonical()) {
+        Ok(parsed) if parsed.is_ipv4() => Ok(parsed),
+        Ok(_) => Err(format!("The provided IP address is not an IPv4 address: {}", ip)),
+        Err(_) => Err(format!("Invalid IP address provided: {}", ip)),
+    }
+}
+
+// the address without any %zone, for everything that only cares
+// about which host it is
+pub fn strip_zone(ip: &str) -> &str {
+    ip.split('%').next().unwrap_or(ip)
+}
+
+// an address in the same form the reports use, which for IPv6 is the
+// compressed RFC 5952 form, so ::1 and 0:0:0:0:0:0:0:1 come out the
+// same, and an IPv4-mapped address is its IPv4 address. Anything that
+// isn't an address is left as it is
+pub fn normalize_ip(ip: &str) -> String {
+    let Ok(parsed) = strip_zone(ip).parse::<IpAddr>().map(|parsed| parsed.to_canonical()) else {
+        return ip.to_string();
+    };
+
+    match ip.split_once('%') {
+        Some((_, zone)) => format!("{}%{}", parsed, zone),
+        None => parsed.to_string(),
+    }
+}
+
+// IPv6 is only scanned for link-local addresses with a zone, like
+// fe80::1%eth0, since those are directly attached neighbors that
+// can't be reached over IPv4. The zone is kept in the target so the
+// connect can use it
+pub fn parse_scoped_ip(ip: &str) -> Result<Target, String> {
+    let Some((address, zone)) = ip.split_once('%') else {
+        return Err(format!("Invalid IP address provided: {}", ip));
+    };
+
+    match address.parse::<IpAddr>() {
+        Ok(IpAddr::V6(parsed)) if parsed.segments()[0] & 0xffc0 == 0xfe80 && !zone.is_empty() => {
+            let ip: String = format!("{}%{}", parsed, zone);
+            if target_address(&ip, 0).is_err() {
+                return Err(format!("No network interface for the zone in {}", ip));
+            }
+
+            Ok(Target { ip, domain: None, fallbacks: Vec::new(), port: None })
+        }
+        Ok(_) => Err(format!("Only IPv6 link-local addresses can have a zone: {}", ip)),
+        Err(_) => Err(format!("Invalid IP address provided: {}", ip)),
+    }
+}
+
+// a target given as an address, either an IPv4 address or a zoned
+// IPv6 link-local one
+pub fn parse_ip_target(ip: &str, domain: Option<String>) -> Result<Target, String> {
+    if ip.contains('%') {
+        return parse_scoped_ip(ip).map(|target| Target { domain, ..target });
+    }
+
+    parse_ip(ip).map(|ip| Target::from_ip(ip, domain))
+}
+
+// whether ip is a range like 192.168.1.10-192.168.1.50 rather than
+// a single address
+pub fn is_ip_range(ip: &str) -> bool {
+    ip.contains('-')
+}
+
+// expands a start-end range into a target for every address from
+// start to end, both included. Both ends have to be IPv4 since that's
+// all that can be scanned without a zone, and a range bigger than
+// limit is refused before it gets expanded
+pub fn parse_ip_range(range: &str, limit: u64) -> Result<Vec<Target>, String> {
+    let Some((start, end)) = range.split_once('-') else {
+        return Err(format!("Invalid IP range, expected start-end: {}", range));
+    };
+
+    let parse_end = |ip: &str| -> Result<IpAddr, String> { ip.trim().parse::<IpAddr>().map(|ip| ip.to_canonical()).map_err(|_| format!("Invalid IP address in range {}: {}", range, ip.trim())) };
+
+    let (start, end): (Ipv4Addr, Ipv4Addr) = match (parse_end(start)?, parse_end(end)?) {
+        (IpAddr::V4(start), IpAddr::V4(end)) => (start, end),
+        (IpAddr::V6(_), IpAddr::V6(_)) => return Err(format!("Only IPv4 ranges can be scanned: {}", range)),
+        _ => return Err(format!("Both ends of an IP range have to be the same family: {}", range)),
+    };
+
+    if start > end {
+        return Err(format!("The start of an IP range has to come before the end: {}", range));
+    }
+
+    let size: u64 = u64::from(u32::from(end) - u32::from(start)) + 1;
+    if size > limit {
+        return Err(format!("Refusing to expand {} addresses in {}, which is more than --ports-expand-limit {} (pass --force to scan anyway)", size, range, limit));
+    }
+
+    Ok((u32::from(start)..=u32::from(end)).map(|ip| Target::from_ip(IpAddr::V4(Ipv4Addr::from(ip)), None)).collect())
+}
+
+// whether a line of a targets file is an address rather than a
+// hostname that has to be looked up
+fn is_address(line: &str) -> bool {
+    line.parse::<IpAddr>().is_ok() || line.contains('%')
+}
+
+// the targets in a list of them, without blank lines or anything
+// after a #
+fn target_lines<'a>(lines: impl IntoIterator<Item = &'a str>) -> Vec<&'a str> {
+    lines.into_iter().map(|line| line.split('#').next().unwrap_or("").trim()).filter(|line| !line.is_empty()).collect()
+}
+
+// looks up every hostname in a list of targets the way parse_targets
+// would without turning them into targets, for --resolve-only. Each
+// hostname comes back with every address it resolved to or why it
+// didn't, addresses in the list are left out since there's nothing to
+// look up
+pub async fn resolve_hostnames<'a>(lines: impl IntoIterator<Item = &'a str>, resolver: &mut Resolver) -> Vec<(&'a str, Result<Vec<IpAddr>, String>)> {
+    let domains: Vec<&str> = target_lines(lines).into_iter().filter(|line| !is_address(line)).collect();
+    let answers: Vec<Result<Vec<IpAddr>, String>> = resolver.resolve_all(&domains).await;
+
+    domains.into_iter().zip(answers).collect()
+}
+
+// parses a list of targets, one IP address or hostname per line,
+// blank lines and anything after a # are ignored. Every hostname is
+// looked up at once before any of them are used, and a target that
+// can't be used is reported and skipped instead of stopping the whole
+// run
+pub async fn parse_targets<'a>(lines: impl IntoIterator<Item = &'a str>, resolver: &mut Resolver) -> Vec<Target> {
+    let lines: Vec<&str> = target_lines(lines);
+
+    let domains: Vec<&str> = lines.iter().copied().filter(|line| !is_address(line)).collect();
+    let mut answers = resolver.resolve_all(&domains).await.into_iter();
+
+    let mut targets: Vec<Target> = Vec::new();
+    for line in lines {
+        let target: Result<Target, String> = if is_address(line) {
+            parse_ip_target(line, None)
+        } else {
+            answers.next().unwrap().map(|ips| domain_target(line, &ips))
+        };
+
+        match target {
+            Ok(target) => targets.push(target),
+            Err(err) => eprintln!("Skipping target: {}", err),
+        }
+    }
+
+    targets
+}
+
+// reads a targets file in the same layout parse_targets takes
+pub async fn read_targets_file(path: &str, resolver: &mut Resolver) -> Result<Vec<Target>, String> {
+    let contents: String = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|err| format!("Failed to read targets file {}: {}", path, err))?;
+
+    Ok(parse_targets(contents.lines(), resolver).await)
+}
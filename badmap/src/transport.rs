@@ -0,0 +1,151 @@
+// How a scan reaches the ports it scans. Connecting directly over TCP
+// and going through an SSH jump host are both transports, and so is
+// MockTransport, which never touches the network and gives back
+// whatever each port was scripted to, for testing code built on the
+// scanning engine
+use crate::{connect, ScanOptions};
+
+use futures::future::{self, BoxFuture};
+use futures::FutureExt;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Cursor, ErrorKind};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+// an open connection to a port, read from for the banner and shut
+// down for a graceful close
+pub trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+// opens connections for a scan. The connect timeout is applied on top
+// by the scan, so a transport can take as long as it likes, and the
+// error kind it fails with decides whether the port is closed or
+// filtered the same as for a direct connect
+pub trait Transport: fmt::Debug + Send + Sync {
+    fn connect<'a>(&'a self, address: SocketAddr, options: &'a ScanOptions) -> BoxFuture<'a, io::Result<Box<dyn Connection>>>;
+}
+
+// connects straight to the target, binding the source port and
+// address from the options if they were given. This is what scans use
+// unless there's a jump host or another transport in the options
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TcpTransport;
+
+impl Transport for TcpTransport {
+    fn connect<'a>(&'a self, address: SocketAddr, options: &'a ScanOptions) -> BoxFuture<'a, io::Result<Box<dyn Connection>>> {
+        async move { Ok(Box::new(connect(address, options).await?) as Box<dyn Connection>) }.boxed()
+    }
+}
+
+// what a port on a MockTransport does when it's connected to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MockPort {
+    // accepts the connection, sending the banner if there is one
+    Open(Option<String>),
+
+    // accepts the connection and resets it on the first read, like a
+    // service that hangs up on whoever it doesn't like
+    Reset,
+
+    // refuses the connection
+    Closed,
+
+    // never answers, so the connect runs into the scan's timeout
+    Timeout,
+
+    // fails the connect with this error
+    Error(ErrorKind),
+
+    // fails the connect the way it does when the process has run out
+    // of file descriptors
+    OutOfFiles,
+}
+
+// a transport that answers from a script instead of the network, by
+// port, so the same script covers every target. Ports that weren't
+// scripted are closed
+#[derive(Debug, Clone, Default)]
+pub struct MockTransport {
+    ports: HashMap<u16, MockPort>,
+
+    // how long every connect takes to answer, so latencies and timeout
+    // scaling have something to go on
+    latency: Duration,
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        MockTransport::default()
+    }
+
+    // scripts what connecting to port does
+    pub fn port(mut self, port: u16, behavior: MockPort) -> Self {
+        self.ports.insert(port, behavior);
+        self
+    }
+
+    // a port that's open and sends banner
+    pub fn open(self, port: u16, banner: Option<&str>) -> Self {
+        self.port(port, MockPort::Open(banner.map(|banner| banner.to_string())))
+    }
+
+    pub fn latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+}
+
+impl Transport for MockTransport {
+    fn connect<'a>(&'a self, address: SocketAddr, _options: &'a ScanOptions) -> BoxFuture<'a, io::Result<Box<dyn Connection>>> {
+        let behavior: MockPort = self.ports.get(&address.port()).cloned().unwrap_or(MockPort::Closed);
+        let latency: Duration = self.latency;
+
+        async move {
+            if behavior != MockPort::Timeout {
+                tokio::time::sleep(latency).await;
+            }
+
+            match behavior {
+                // a cursor reads back the banner and then looks closed,
+                // which is all a scan needs from the other side
+                MockPort::Open(banner) => Ok(Box::new(Cursor::new(banner.unwrap_or_default().into_bytes())) as Box<dyn Connection>),
+                MockPort::Reset => Ok(Box::new(ResetConnection) as Box<dyn Connection>),
+                MockPort::Closed => Err(ErrorKind::ConnectionRefused.into()),
+                MockPort::Timeout => future::pending().await,
+                MockPort::Error(kind) => Err(kind.into()),
+                MockPort::OutOfFiles => Err(io::Error::from_raw_os_error(libc::EMFILE)),
+            }
+        }
+        .boxed()
+    }
+}
+
+// the other side of a MockPort::Reset connection, which fails every
+// read and write with a reset
+struct ResetConnection;
+
+impl AsyncRead for ResetConnection {
+    fn poll_read(self: Pin<&mut Self>, _cx: &mut Context<'_>, _buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Err(ErrorKind::ConnectionReset.into()))
+    }
+}
+
+impl AsyncWrite for ResetConnection {
+    fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, _buf: &[u8]) -> Poll<io::Result<usize>> {
+        Poll::Ready(Err(ErrorKind::ConnectionReset.into()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Err(ErrorKind::ConnectionReset.into()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Err(ErrorKind::ConnectionReset.into()))
+    }
+}